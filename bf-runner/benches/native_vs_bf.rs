@@ -0,0 +1,173 @@
+//! Runs the native analyzer and `program.bf` over the same slice of a capture, checking that
+//! both give the same output and reporting how much slower the brainfuck version is.
+//!
+//! The capture is `packet-storm.pcap` in the repo root unless `PACKET_STORM_PCAP` is set, and
+//! only its first `BENCH_PACKETS` (default 40) packets are used. Some small captures built here
+//! are always checked too, even without one. `program.bf` is the committed copy, so regenerate it
+//! first (`cargo run -r -p bf-runner`) after changing the generator.
+
+use std::{
+    cell::RefCell,
+    io::{Cursor, Write},
+    rc::Rc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use bf_runner::{AssertionMode, FlushPolicy, Interpreter, Program};
+use packet_storm::{Capture, CaptureWriter, FrameIter, Summary};
+
+const ITERATIONS: u32 = 10;
+
+fn main() -> anyhow::Result<()> {
+    // Without `--bench` (e.g. `cargo test --benches`), just check the outputs match once
+    let bench = std::env::args().any(|arg| arg == "--bench");
+    let root = concat!(env!("CARGO_MANIFEST_DIR"), "/..");
+
+    let source = fs_err::read_to_string(format!("{root}/program.bf"))?;
+    let mut runner = Runner::new(Program::from_text(&source)?);
+    for (name, data) in small_captures()? {
+        runner.check(&data).with_context(|| format!("{name} capture"))?;
+    }
+
+    let pcap = std::env::var("PACKET_STORM_PCAP").unwrap_or_else(|_| format!("{root}/packet-storm.pcap"));
+    let data = match fs_err::read(&pcap) {
+        Ok(data) => data,
+        Err(e) if !bench => {
+            eprintln!("Skipping comparison: {e}");
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let packets = match std::env::var("BENCH_PACKETS") {
+        Ok(n) => n.parse().with_context(|| format!("invalid BENCH_PACKETS {n:?}"))?,
+        Err(_) => 40,
+    };
+    let data = Capture::new(&data)?.prefix(packets).to_vec();
+
+    runner.check(&data).with_context(|| format!("first {packets} packets from {pcap}"))?;
+    if !bench {
+        return Ok(());
+    }
+
+    let native_time = time(|| native(&data).map(drop))?;
+    let bf_time = time(|| runner.run(&data).map(drop))?;
+    println!("{packets} packets from {pcap}");
+    println!("native:    {native_time:?}/iter");
+    println!("brainfuck: {bf_time:?}/iter");
+    println!("ratio:     {:.0}x", bf_time.as_secs_f64() / native_time.as_secs_f64());
+
+    Ok(())
+}
+
+fn time(mut f: impl FnMut() -> anyhow::Result<()>) -> anyhow::Result<Duration> {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f()?;
+    }
+    Ok(start.elapsed() / ITERATIONS)
+}
+
+// The analyzer's summary, which displays in `program.bf`'s format
+fn native(data: &[u8]) -> anyhow::Result<String> {
+    let pcap = Capture::new(data)?;
+    let mut summary = Summary::default();
+    for packet in pcap.records().filter_ip(|_| true) {
+        summary.add(&packet);
+    }
+    Ok(summary.to_string())
+}
+
+struct Runner {
+    interpreter: Interpreter,
+    output: SharedOutput,
+}
+
+impl Runner {
+    fn new(program: Program) -> Self {
+        let output = SharedOutput::default();
+        let mut interpreter = Interpreter::new(program, Cursor::new(vec![]));
+        interpreter.set_output(output.clone());
+        interpreter.set_assertions(AssertionMode::Error);
+        interpreter.set_flush_policy(FlushPolicy::OnEnd);
+        Self { interpreter, output }
+    }
+
+    fn run(&mut self, data: &[u8]) -> anyhow::Result<String> {
+        self.output.0.borrow_mut().clear();
+        self.interpreter.reset(Cursor::new(data.to_vec()));
+        self.interpreter.run()?;
+        String::from_utf8(self.output.0.borrow().clone()).context("program.bf wrote invalid UTF-8")
+    }
+
+    fn check(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let expected = native(data)?;
+        let bf = self.run(data)?;
+        if expected != bf {
+            bail!("outputs differ\n--- native ---\n{expected}--- brainfuck ---\n{bf}");
+        }
+        Ok(())
+    }
+}
+
+// Captures with the short totals and ties that a slice of a real capture rarely has, as
+// (description, capture)
+fn small_captures() -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    const UDP: u8 = 17;
+    const TCP: u8 = 6;
+
+    let mut captures = vec![];
+    // A single UDP packet with that much IP-level data, covering each number of digits
+    for ip_data in [8, 9, 10, 18, 49, 99, 100, 255, 256, 1000] {
+        captures.push((format!("{ip_data} byte"), capture(&[frame(UDP, 1, ip_data)])?));
+    }
+    captures.push((
+        "tied destinations".to_owned(),
+        capture(&[frame(TCP, 1, 20), frame(UDP, 2, 30), frame(TCP, 3, 40), frame(UDP, 2, 8), frame(TCP, 1, 25)])?,
+    ));
+    Ok(captures)
+}
+
+fn capture(frames: &[Vec<u8>]) -> anyhow::Result<Vec<u8>> {
+    const ETHERNET: u32 = 1;
+
+    let mut writer = CaptureWriter::new(vec![], ETHERNET)?;
+    for frame in frames {
+        writer.write_frame(SystemTime::UNIX_EPOCH, frame)?;
+    }
+    writer.into_inner()
+}
+
+// An Ethernet frame of an IPv4 packet to 10.0.0.`dest`, with `ip_data` bytes after the IP header
+fn frame(protocol: u8, dest: u8, ip_data: u16) -> Vec<u8> {
+    let mut frame = vec![0; 12]; // MAC addresses
+    frame.extend(0x0800_u16.to_be_bytes());
+
+    frame.extend([0x45, 0]); // Version 4, 5 word header
+    frame.extend((20 + ip_data).to_be_bytes());
+    frame.extend([0; 4]); // Identification, flags and fragment offset
+    frame.extend([64, protocol, 0, 0]); // TTL, protocol and checksum
+    frame.extend([10, 0, 0, 100, 10, 0, 0, dest]);
+
+    let mut transport = vec![0; ip_data.into()];
+    match protocol {
+        17 => transport[4..6].copy_from_slice(&ip_data.to_be_bytes()),
+        _ => transport[12] = 5 << 4, // Data offset
+    }
+    frame.extend(transport);
+    frame
+}
+
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -4,65 +4,299 @@ use std::{
     panic::Location,
 };
 
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 
-use crate::{Instruction, Marker};
+use crate::{CommentLevel, Instruction, Marker};
 
 pub mod num;
 
-#[derive(Debug, Clone)]
+/// Builds an [`Item::Sequence`] from brainfuck string literals (parsed with [`Item::parse`],
+/// panicking if invalid) and `{ ... }` blocks holding anything that converts into an [`Item`],
+/// e.g. `bf!(">>+++", { Item::add_marker("x") }, "[<->]")`.
+#[macro_export]
+macro_rules! bf {
+    (@part $s:literal) => {
+        $crate::build::Item::parse($s).expect("should be valid")
+    };
+    (@part { $e:expr }) => {
+        $crate::build::Item::from($e)
+    };
+    ($($part:tt),* $(,)?) => {
+        $crate::build::Item::Sequence(vec![$($crate::bf!(@part $part)),*])
+    };
+}
+
+/// Items compare structurally, e.g. for checking what a helper builds. Custom actions are only equal
+/// if both are [named](Item::named_custom) and have the same name, as closures can't be compared, so
+/// an unnamed one isn't even equal to itself.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Item {
+    /// Builds to nothing, for when an item is only sometimes needed
+    Nop,
     Sequence(Vec<Self>),
     Direct(Instruction),
     Loop(Loop),
     Repeat { item: Box<Self>, n: usize },
-    Comment(String, u8),
+    Comment(String, CommentLevel),
     EndComment,
     Custom(#[allow(private_interfaces)] Box<dyn CustomAction>),
 }
 
 impl Item {
+    /// Parses brainfuck source, skipping whitespace (so it can be laid out over several lines), but
+    /// treating any other non-instruction byte as an error.
+    ///
+    /// The exception is a `//` comment running to the end of its line, as written by
+    /// [`Program::as_text`](crate::Program::as_text), which is kept as an [`Item::Comment`] with
+    /// level 0.
     pub fn parse(s: &str) -> anyhow::Result<Self> {
-        Ok(Self::Sequence(
-            s.bytes()
-                .map(|b| {
-                    Instruction::from_byte(b)
-                        .map(Self::Direct)
-                        .ok_or_else(|| anyhow!("unknown byte 0x{b:02X}"))
-                })
-                .collect::<Result<_, _>>()?,
-        ))
-    }
-
-    pub fn run(self, tape: super::Tape<'_>, position: usize, markers: &mut HashMap<String, Marker>) {
-        if let Self::Custom(action) = self {
-            action.act(tape, position, markers)
+        let bytes = s.as_bytes();
+        let mut items = vec![];
+        let mut i = 0;
+        while let Some(&b) = bytes.get(i) {
+            if bytes[i..].starts_with(b"//") {
+                let end = bytes[i..].iter().position(|&c| c == b'\n').map_or(bytes.len(), |nl| i + nl);
+                items.push(Self::Comment(s[i + 2..end].trim().to_owned(), CommentLevel::PARSED));
+                items.push(Self::EndComment);
+                i = end;
+                continue;
+            }
+            if !b.is_ascii_whitespace() {
+                let instruction = Instruction::from_byte(b).ok_or_else(|| {
+                    let before = &bytes[..i];
+                    let line = before.iter().filter(|&&c| c == b'\n').count() + 1;
+                    let column = i - before.iter().rposition(|&c| c == b'\n').map_or(0, |nl| nl + 1) + 1;
+                    anyhow!("unknown byte 0x{b:02X} at offset {i} (line {line}, column {column})")
+                })?;
+                items.push(Self::Direct(instruction));
+            }
+            i += 1;
+        }
+        Ok(Self::Sequence(items))
+    }
+
+    /// As [`Item::parse`], but any other bytes are comments, as in most brainfuck implementations.
+    ///
+    /// Each run of comment text is kept (with surrounding whitespace trimmed) as an
+    /// [`Item::Comment`] with level 0, so it's only printed at the lowest print level.
+    pub fn parse_lenient(s: &str) -> Self {
+        fn push_comment(items: &mut Vec<Item>, comment: &mut String) {
+            let text = comment.trim();
+            if !text.is_empty() {
+                items.push(Item::Comment(text.to_owned(), CommentLevel::PARSED));
+                items.push(Item::EndComment);
+            }
+            comment.clear();
+        }
+
+        let mut items = vec![];
+        let mut comment = String::new();
+        for c in s.chars() {
+            match u8::try_from(c).ok().and_then(Instruction::from_byte) {
+                Some(ins) => {
+                    push_comment(&mut items, &mut comment);
+                    items.push(Self::Direct(ins));
+                }
+                None => comment.push(c),
+            }
+        }
+        push_comment(&mut items, &mut comment);
+        Self::Sequence(items)
+    }
+
+    /// Runs a custom action directly, e.g. from inside another one. Any other item does nothing.
+    pub fn run(self, tape: super::Tape<'_>, position: usize, markers: &mut HashMap<String, Marker>) -> anyhow::Result<()> {
+        match self {
+            Self::Custom(action) => action.act(tape, position, markers),
+            _ => Ok(()),
+        }
+    }
+
+    /// Merges nested [`Item::Sequence`]s (including those inside loops and repeats) into a single
+    /// level and drops any [`Item::Nop`]s, which builds to exactly the same actions with less recursion.
+    ///
+    /// Works through the tree with its own stack rather than recursing, so any depth of nesting
+    /// can be flattened.
+    pub fn flatten(self) -> Self {
+        // What to rebuild around an item's flattened contents
+        enum Parent {
+            Sequence,
+            Loop(Loop),
+            Repeat { n: usize, sequence: bool },
+        }
+
+        fn open(item: Item) -> Result<(Parent, Vec<Item>), Item> {
+            match item {
+                Item::Sequence(items) => Ok((Parent::Sequence, items)),
+                Item::Loop(mut l) => {
+                    let body = std::mem::take(&mut l.body);
+                    Ok((Parent::Loop(l), body))
+                }
+                Item::Repeat { item, n } => match *item {
+                    Item::Sequence(items) => Ok((Parent::Repeat { n, sequence: true }, items)),
+                    other => Ok((Parent::Repeat { n, sequence: false }, vec![other])),
+                },
+                other => Err(other),
+            }
+        }
+
+        fn close(parent: Parent, mut flat: Vec<Item>) -> Item {
+            match parent {
+                Parent::Sequence => Item::Sequence(flat),
+                Parent::Loop(l) => Item::Loop(Loop { body: flat, ..l }),
+                Parent::Repeat { n, sequence: true } => Item::Repeat {
+                    item: Box::new(Item::Sequence(flat)),
+                    n,
+                },
+                Parent::Repeat { n, sequence: false } => Item::Repeat {
+                    item: Box::new(flat.pop().unwrap_or(Item::Nop)),
+                    n,
+                },
+            }
+        }
+
+        let (parent, items) = match open(self) {
+            Ok(opened) => opened,
+            Err(item) => return item,
+        };
+        let mut stack = vec![(parent, items.into_iter(), vec![])];
+        loop {
+            let (_, items, flat) = stack.last_mut().unwrap();
+            match items.next() {
+                Some(Item::Nop) => {}
+                Some(item) => match open(item) {
+                    Ok((parent, items)) => stack.push((parent, items.into_iter(), vec![])),
+                    Err(item) => flat.push(item),
+                },
+                None => {
+                    let (parent, _, flat) = stack.pop().unwrap();
+                    let Some((_, _, outer)) = stack.last_mut() else {
+                        return close(parent, flat);
+                    };
+                    match parent {
+                        Parent::Sequence => outer.extend(flat),
+                        parent => outer.push(close(parent, flat)),
+                    }
+                }
+            }
         }
     }
 
+    /// Repeats the item `n` times. Repeating it 0 or 1 times gives [`Item::Nop`] or the item
+    /// itself, rather than boxing it up in an [`Item::Repeat`].
     pub fn repeat(self, n: usize) -> Self {
-        Self::Repeat { item: Box::new(self), n }
+        match n {
+            0 => Self::Nop,
+            1 => self,
+            n => Self::Repeat { item: Box::new(self), n },
+        }
+    }
+
+    /// Runs `body` exactly `count` times, using the current cell as the counter, i.e. `+++[-body]`
+    /// for a count of 3. The counter must start out zero, and is left zeroed.
+    ///
+    /// `body` starts and ends on the counter, so it must be balanced (which [`Item::check_balance`]
+    /// checks as for any loop), and must not change the counter's value.
+    #[track_caller]
+    pub fn counted_loop(count: u8, body: impl Into<Item>) -> Self {
+        let mut items = vec![];
+        if cfg!(debug_assertions) {
+            items.push(Item::assert_zero(0, "loop counter"));
+        }
+        items.push(Item::from(Instruction::Inc).repeat(count.into()));
+        items.push(Loop::new(vec![Instruction::Dec.into(), body.into()]).into());
+        Self::Sequence(items)
+    }
+
+    /// Prints `s`, which must be ASCII, using the current cell and the one to its right as scratch.
+    /// Both must start out zero, and are left zeroed with the pointer back on the current cell.
+    ///
+    /// Each character is built up in the right-hand cell, either from the previous character if
+    /// it's close enough, or from zero with a multiplication loop.
+    pub fn emit_str(s: &str) -> Self {
+        // The cheapest way to build `c` from zero, as `a * b + r` (or just `c` if `a` is 0), and
+        // how many instructions that takes
+        fn from_zero(c: u8) -> ((usize, usize, isize), usize) {
+            let loops = (1..=c as usize).map(|a| {
+                let b = (c as usize + a / 2) / a;
+                let r = c as isize - (a * b) as isize;
+                // The `<[><-]>` around the additions is another 7
+                ((a, b, r), a + b + r.unsigned_abs() + 7)
+            });
+            std::iter::once(((0, 0, 0), c as usize)).chain(loops).min_by_key(|&(_, cost)| cost).unwrap()
+        }
+        fn change(by: isize) -> Item {
+            let insn = if by >= 0 { Instruction::Inc } else { Instruction::Dec };
+            Item::from(insn).repeat(by.unsigned_abs())
+        }
+
+        assert!(s.is_ascii(), "can only emit ASCII strings");
+        let mut items = vec![Instruction::Right.into()];
+        let mut current = 0;
+        for c in s.bytes() {
+            let ((a, b, r), cost) = from_zero(c);
+            let reset = if current == 0 { 0 } else { 3 };
+            let delta = c as isize - current as isize;
+            if delta.unsigned_abs() <= reset + cost {
+                items.push(change(delta));
+            } else {
+                if current != 0 {
+                    items.push(zero_cell());
+                }
+                if a == 0 {
+                    items.push(change(c as isize));
+                } else {
+                    items.extend([
+                        Instruction::Left.into(),
+                        change(a as isize),
+                        Loop::new(vec![Instruction::Right.into(), change(b as isize), Instruction::Left.into(), Instruction::Dec.into()]).into(),
+                        Instruction::Right.into(),
+                        change(r),
+                    ]);
+                }
+            }
+            items.push(Instruction::Output.into());
+            current = c;
+        }
+        if current != 0 {
+            items.push(zero_cell());
+        }
+        items.push(Instruction::Left.into());
+        Self::Sequence(items)
     }
 
-    pub fn comment(self, comment: impl Into<String>, level: u8) -> Self {
-        Self::Sequence(vec![Self::Comment(comment.into(), level), self, Self::EndComment])
+    pub fn comment(self, comment: impl Into<String>, level: impl Into<CommentLevel>) -> Self {
+        Self::Sequence(vec![Self::Comment(comment.into(), level.into()), self, Self::EndComment])
     }
 
-    pub fn custom(f: impl for<'a> Fn(super::Tape<'a>, usize, &mut HashMap<String, Marker>) + 'static + Clone) -> Self {
+    /// An action run by the interpreter rather than built into the program. Returning an error
+    /// is an assertion failure, handled as set by [`Interpreter::set_assertions`](super::Interpreter::set_assertions).
+    pub fn custom(f: impl for<'a> Fn(super::Tape<'a>, usize, &mut HashMap<String, Marker>) -> anyhow::Result<()> + 'static + Clone) -> Self {
         Self::Custom(Box::new(f))
     }
 
+    /// As [`Item::custom`], but with a name that is shown by [`Item::tree_string`]
+    pub fn named_custom(
+        name: impl Into<String>,
+        f: impl for<'a> Fn(super::Tape<'a>, usize, &mut HashMap<String, Marker>) -> anyhow::Result<()> + 'static + Clone,
+    ) -> Self {
+        Self::Custom(Box::new(Named { name: name.into(), action: f }))
+    }
+
     #[track_caller]
     pub fn add_marker(name: impl Into<String>) -> Self {
         let caller = Location::caller();
         let name = name.into();
-        Self::custom(move |_, position, markers| {
+        Self::named_custom(format!("add marker {name:?}"), move |tape, position, markers| {
+            if let Some(old) = markers.get(&name) {
+                bail!("[{caller}] marker {name:?} already exists\n[{}] placed it at {}\n{tape}", old.created, old.at);
+            }
             let marker = Marker {
                 at: position,
                 created: caller,
             };
-            let old = markers.insert(name.clone(), marker);
-            assert!(old.is_none(), "marker {name:?} already exists")
+            markers.insert(name.clone(), marker);
+            Ok(())
         })
     }
 
@@ -71,8 +305,8 @@ impl Item {
         let caller = Location::caller();
         let name = name.into();
         let comment = comment.into();
-        Self::custom(move |tape, position, markers| {
-            let marker = markers.get(&name).expect("marker does not exist");
+        Self::named_custom(format!("assert marker {name:?} {offset:+}"), move |tape, position, markers| {
+            let marker = markers.get(&name).ok_or_else(|| anyhow!("[{caller}] marker {name:?} does not exist"))?;
             let base = marker.at;
             let expected = if offset >= 0 {
                 base + offset as usize
@@ -80,68 +314,278 @@ impl Item {
                 base - offset.unsigned_abs()
             };
             if position != expected {
-                println!("mismatched marker, offset {offset}");
-                println!("[{}] placed marker {name:?} at {}", marker.created, marker.at);
-                println!("expected: {expected}");
-                println!("found   : {position}");
-                println!("source  : {comment}");
-                println!("[{caller}] misplaced");
-                println!("{tape}");
-                std::process::exit(1);
+                bail!(
+                    "mismatched marker, offset {offset}\n\
+                    [{}] placed marker {name:?} at {}\n\
+                    expected: {expected}\n\
+                    found   : {position}\n\
+                    source  : {comment}\n\
+                    [{caller}] misplaced\n\
+                    {tape}",
+                    marker.created,
+                    marker.at
+                );
             }
+            Ok(())
         })
     }
 
     #[track_caller]
     pub fn remove_marker(name: impl Into<String>) -> Self {
+        let caller = Location::caller();
         let name = name.into();
-        Self::custom(move |_, _, markers| {
-            markers.remove(&name).expect("marker does not exist");
+        Self::named_custom(format!("remove marker {name:?}"), move |_, _, markers| {
+            markers.remove(&name).ok_or_else(|| anyhow!("[{caller}] marker {name:?} does not exist"))?;
+            Ok(())
         })
     }
 
     #[track_caller]
     pub fn halt() -> Item {
         let caller = Location::caller();
-        Item::custom(move |tape, _, _| {
-            println!("[{caller}] - explicit halt");
-            println!("{tape}");
-            std::process::exit(1)
+        Item::named_custom("halt", move |tape, _, _| bail!("[{caller}] - explicit halt\n{tape}"))
+    }
+
+    /// Asserts that the cell `offset` from the pointer is zero, e.g. scratch space that should have
+    /// been cleaned up.
+    #[track_caller]
+    pub fn assert_zero(offset: isize, message: impl Into<String>) -> Item {
+        let caller = Location::caller();
+        let message = message.into();
+        Item::named_custom(format!("assert {offset:+} is zero ({message})"), move |tape, pointer, _| {
+            let cell = pointer.checked_add_signed(offset).ok_or_else(|| anyhow!("[{caller}] - cell {offset:+} is off the tape"))?;
+            // Cells the program hasn't reached yet are zero
+            let value = tape.get(cell).copied().unwrap_or(0);
+            if value != 0 {
+                bail!(
+                    "[{caller}] - nonzero cell\n\
+                    cell   : {cell}\n\
+                    value  : {value}\n\
+                    source : {message}\n\
+                    {tape}"
+                );
+            }
+            Ok(())
         })
     }
 
+    /// Asserts that the pointer is on `cell`, counted from the starting cell (see [`crate::Tape::position`]).
     #[track_caller]
     pub fn assert_position(cell: usize, message: impl Into<String>) -> Item {
         let caller = Location::caller();
         let message = message.into();
-        Item::custom(move |tape, pointer, _| {
-            if pointer != cell {
-                println!("[{caller}] - mismatched positions");
-                println!("expected: {cell}");
-                println!("actual  : {pointer}");
-                println!("source  : {message}");
-                println!("{tape}");
-                std::process::exit(1)
+        Item::named_custom(format!("assert position {cell} ({message})"), move |tape, _, _| {
+            let position = tape.position();
+            if usize::try_from(position) != Ok(cell) {
+                bail!(
+                    "[{caller}] - mismatched positions\n\
+                    expected: {cell}\n\
+                    actual  : {position}\n\
+                    source  : {message}\n\
+                    {tape}"
+                );
             }
+            Ok(())
         })
     }
 }
 
+impl Item {
+    /// Statically checks that every [`Loop`] body returns the pointer to where it started.
+    ///
+    /// Returns the net pointer movement of the whole item, or `None` if it depends on the data
+    /// (i.e. it contains a scan such as `[>>>>]` or a loop marked with [`Loop::unbalanced`]).
+    /// Custom actions are treated as not moving the pointer, and loops written directly as `[`
+    /// and `]` (e.g. from [`Item::parse`]) aren't checked, so are treated as scans if they move.
+    pub fn check_balance(&self) -> anyhow::Result<Option<isize>> {
+        let mut errors = vec![];
+        let movement = self.net_movement(&mut vec![], &mut errors);
+        if errors.is_empty() {
+            Ok(movement)
+        } else {
+            bail!("{} unbalanced loop[s]:\n{}", errors.len(), errors.join("\n"))
+        }
+    }
+
+    fn net_movement(&self, comments: &mut Vec<String>, errors: &mut Vec<String>) -> Option<isize> {
+        match self {
+            Self::Sequence(items) => {
+                let depth = comments.len();
+                let mut total = Some(0_isize);
+                // The movement up to each unmatched `[` written directly, e.g. by `Item::parse`
+                let mut raw_loops = vec![];
+                for item in items {
+                    match item {
+                        Self::Direct(Instruction::Start) => raw_loops.push(total),
+                        Self::Direct(Instruction::End) if raw_loops.pop().is_some_and(|start| start != total) => total = None,
+                        _ => {}
+                    }
+                    let movement = item.net_movement(comments, errors);
+                    total = total.zip(movement).map(|(a, b)| a + b);
+                }
+                comments.truncate(depth);
+                total
+            }
+            Self::Direct(Instruction::Left) => Some(-1),
+            Self::Direct(Instruction::Right) => Some(1),
+            Self::Direct(_) => Some(0),
+            Self::Loop(Loop {
+                body,
+                unbalanced,
+                created,
+                ..
+            }) => {
+                let depth = comments.len();
+                let movement = body
+                    .iter()
+                    .map(|item| item.net_movement(comments, errors))
+                    .try_fold(0_isize, |acc, it| it.map(|it| acc + it));
+                comments.truncate(depth);
+                match movement {
+                    Some(0) => Some(0),
+                    Some(_) if *unbalanced || body.iter().all(Item::only_moves) => None,
+                    Some(movement) => {
+                        let context = if comments.is_empty() {
+                            "top level".to_owned()
+                        } else {
+                            comments.join(" > ")
+                        };
+                        let location = format!("[{created}]");
+                        if !errors.iter().any(|it| it.starts_with(&location)) {
+                            errors.push(format!("{location} net movement {movement:+} in {context}"));
+                        }
+                        None
+                    }
+                    None => None,
+                }
+            }
+            Self::Repeat { item, n } => item.net_movement(comments, errors).map(|it| it * *n as isize),
+            Self::Comment(comment, _) => {
+                comments.push(comment.clone());
+                Some(0)
+            }
+            Self::EndComment => {
+                comments.pop();
+                Some(0)
+            }
+            Self::Custom(_) | Self::Nop => Some(0),
+        }
+    }
+
+    /// Renders the structure of the item (rather than the instructions it builds to) for debugging
+    pub fn tree_string(&self) -> String {
+        let mut s = String::new();
+        let _ = self.write_tree(&mut s, 0);
+        s
+    }
+
+    fn write_tree(&self, s: &mut String, depth: usize) -> std::fmt::Result {
+        use std::fmt::Write as _;
+
+        let indent = "  ".repeat(depth);
+        match self {
+            Self::Sequence(items) => {
+                writeln!(s, "{indent}Sequence")?;
+                Self::write_tree_items(items, s, depth + 1)
+            }
+            Self::Direct(ins) => writeln!(s, "{indent}{}", ins.as_char()),
+            Self::Loop(Loop { body, unbalanced, .. }) => {
+                writeln!(s, "{indent}Loop{}", if *unbalanced { " (unbalanced)" } else { "" })?;
+                Self::write_tree_items(body, s, depth + 1)
+            }
+            Self::Repeat { item, n } => {
+                writeln!(s, "{indent}Repeat{{n={n}}}")?;
+                item.write_tree(s, depth + 1)
+            }
+            Self::Comment(comment, level) => writeln!(s, "{indent}Comment({}) {comment:?}", level.0),
+            Self::EndComment => writeln!(s, "{indent}EndComment"),
+            Self::Custom(custom) => writeln!(s, "{indent}Custom {}", custom.name().unwrap_or("<unnamed>")),
+            Self::Nop => writeln!(s, "{indent}Nop"),
+        }
+    }
+
+    // Runs of plain instructions are shown on one line
+    fn write_tree_items(items: &[Self], s: &mut String, depth: usize) -> std::fmt::Result {
+        use std::fmt::Write as _;
+
+        let indent = "  ".repeat(depth);
+        let mut run = String::new();
+        for item in items {
+            if let Self::Direct(ins) = item {
+                run.push(ins.as_char());
+                continue;
+            }
+            if !run.is_empty() {
+                writeln!(s, "{indent}{run}")?;
+                run.clear();
+            }
+            item.write_tree(s, depth)?;
+        }
+        if !run.is_empty() {
+            writeln!(s, "{indent}{run}")?;
+        }
+        Ok(())
+    }
+
+    // Scans such as `[>>>>]` are data-dependent by design
+    fn only_moves(&self) -> bool {
+        match self {
+            Self::Sequence(items) => items.iter().all(Self::only_moves),
+            Self::Direct(ins) => matches!(ins, Instruction::Left | Instruction::Right),
+            Self::Repeat { item, .. } => item.only_moves(),
+            Self::Comment(..) | Self::EndComment | Self::Custom(_) | Self::Nop => true,
+            Self::Loop(_) => false,
+        }
+    }
+}
+
+/// Loops are equal if their bodies and flags are, regardless of where they were created.
 #[derive(Debug, Clone)]
 pub struct Loop {
     body: Vec<Item>,
     change_indent: bool,
+    unbalanced: bool,
+    created: &'static Location<'static>,
+}
+
+impl PartialEq for Loop {
+    fn eq(&self, other: &Self) -> bool {
+        self.body == other.body && self.change_indent == other.change_indent && self.unbalanced == other.unbalanced
+    }
 }
 
 impl Loop {
+    #[track_caller]
     pub fn new(body: Vec<Item>) -> Self {
-        Self { body, change_indent: false }
+        Self {
+            body,
+            change_indent: false,
+            unbalanced: false,
+            created: Location::caller(),
+        }
     }
 
     pub fn indent(mut self) -> Self {
         self.change_indent = true;
         self
     }
+
+    /// Marks the body as deliberately moving the pointer (e.g. `[>]` or `[-<]`), so that
+    /// [`Item::check_balance`] treats the loop's movement as data-dependent rather than an error.
+    pub fn unbalanced(mut self) -> Self {
+        self.unbalanced = true;
+        self
+    }
+
+    /// The items run on each iteration, e.g. for passes that walk the [`Item`] tree.
+    pub fn body(&self) -> &[Item] {
+        &self.body
+    }
+
+    /// Whether the body is indented by a level in the program's text, as set by [`Loop::indent`].
+    pub fn changes_indent(&self) -> bool {
+        self.change_indent
+    }
 }
 
 impl From<Loop> for Item {
@@ -162,17 +606,45 @@ impl From<Vec<Self>> for Item {
     }
 }
 
+impl From<Vec<Instruction>> for Item {
+    fn from(value: Vec<Instruction>) -> Self {
+        Self::Sequence(value.into_iter().map(Self::Direct).collect())
+    }
+}
+
 pub fn drain(offsets: &[isize], add: bool) -> Item {
+    drain_scaled(&offsets.iter().map(|&offset| (offset, 1)).collect::<Vec<_>>(), add)
+}
+
+/// `drain(&[offset], true)`, for moving the current cell into one that should be empty. In debug
+/// builds, this first asserts that the target is zero, rather than quietly adding to stale data.
+#[track_caller]
+pub fn move_cell(offset: isize) -> Item {
+    let moved = drain(&[offset], true);
+    if !cfg!(debug_assertions) {
+        return moved;
+    }
+    Item::Sequence(vec![Item::assert_zero(offset, "move target"), moved])
+}
+
+/// As [`drain`], but each target is changed by its multiplier for every unit drained, e.g.
+/// `drain_scaled(&[(1, 3)], true)` is `[->+++<]`.
+pub fn drain_scaled(targets: &[(isize, u8)], add: bool) -> Item {
     let mut insns = vec![Instruction::Dec.into()];
     let mut delta = 0;
-    for &offset in offsets {
+    for &(offset, multiplier) in targets {
         let dir = if offset >= 0 { Instruction::Right } else { Instruction::Left };
         insns.push(Item::Repeat {
             item: Box::new(dir.into()),
             n: offset.unsigned_abs(),
         });
-        insns.push(if add { Instruction::Inc } else { Instruction::Dec }.into());
+        insns.push(Item::Repeat {
+            item: Box::new(if add { Instruction::Inc } else { Instruction::Dec }.into()),
+            n: multiplier as usize,
+        });
         delta += offset;
+        // Landing back on the cell being drained would change the loop counter
+        debug_assert_ne!(delta, 0, "drain targets {targets:?} return to the drained cell");
     }
     let dir = if delta >= 0 { Instruction::Left } else { Instruction::Right };
     insns.push(Item::Repeat {
@@ -183,23 +655,48 @@ pub fn drain(offsets: &[isize], add: bool) -> Item {
     Loop::new(insns).into()
 }
 
-#[derive(Debug, Clone)]
+/// Compared as [`Item`]s are.
+#[derive(Debug, Clone, PartialEq)]
 pub enum InterpreterAction {
     Instruction(Instruction),
-    Comment(String, u8),
+    Comment(String, CommentLevel),
     EndComment,
     Indent(bool),
     Custom(#[allow(private_interfaces)] Box<dyn CustomAction>),
 }
 
 pub(crate) trait CustomAction {
-    fn act(&self, tape: super::Tape<'_>, position: usize, markers: &mut HashMap<String, Marker>);
+    fn act(&self, tape: super::Tape<'_>, position: usize, markers: &mut HashMap<String, Marker>) -> anyhow::Result<()>;
 
     fn clone_box(&self) -> Box<dyn CustomAction>;
+
+    fn name(&self) -> Option<&str> {
+        None
+    }
+}
+
+#[derive(Clone)]
+struct Named<F> {
+    name: String,
+    action: F,
 }
 
-impl<T: for<'a> Fn(super::Tape<'a>, usize, &mut HashMap<String, Marker>) + Clone + 'static> CustomAction for T {
-    fn act(&self, tape: super::Tape<'_>, position: usize, markers: &mut HashMap<String, Marker>) {
+impl<F: CustomAction + Clone + 'static> CustomAction for Named<F> {
+    fn act(&self, tape: super::Tape<'_>, position: usize, markers: &mut HashMap<String, Marker>) -> anyhow::Result<()> {
+        self.action.act(tape, position, markers)
+    }
+
+    fn clone_box(&self) -> Box<dyn CustomAction> {
+        Box::new(self.clone())
+    }
+
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+}
+
+impl<T: for<'a> Fn(super::Tape<'a>, usize, &mut HashMap<String, Marker>) -> anyhow::Result<()> + Clone + 'static> CustomAction for T {
+    fn act(&self, tape: super::Tape<'_>, position: usize, markers: &mut HashMap<String, Marker>) -> anyhow::Result<()> {
         self(tape, position, markers)
     }
 
@@ -214,9 +711,19 @@ impl Clone for Box<dyn CustomAction> {
     }
 }
 
+impl PartialEq for Box<dyn CustomAction> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name().is_some_and(|name| other.name() == Some(name))
+    }
+}
+
 impl Debug for Box<dyn CustomAction> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("dyn CustomAction").finish_non_exhaustive()
+        let mut s = f.debug_struct("dyn CustomAction");
+        if let Some(name) = self.name() {
+            s.field("name", &name);
+        }
+        s.finish_non_exhaustive()
     }
 }
 
@@ -245,7 +752,9 @@ impl Buildable for Item {
         match self {
             Self::Sequence(s) => s.build(),
             Self::Direct(i) => i.build(),
-            Self::Loop(Loop { body: inner, change_indent }) => iter_once_if(InterpreterAction::Indent(true), change_indent)
+            Self::Loop(Loop {
+                body: inner, change_indent, ..
+            }) => iter_once_if(InterpreterAction::Indent(true), change_indent)
                 .chain(
                     std::iter::once(Instruction::Start.into())
                         .chain(inner)
@@ -256,11 +765,12 @@ impl Buildable for Item {
                 .collect(),
             Self::Repeat { item, n } => {
                 let item = item.build();
-                std::iter::repeat(item).take(n).flatten().collect()
+                std::iter::repeat_n(item, n).flatten().collect()
             }
             Self::Comment(comment, level) => vec![InterpreterAction::Comment(comment, level)],
             Self::EndComment => vec![InterpreterAction::EndComment],
             Self::Custom(custom) => vec![InterpreterAction::Custom(custom)],
+            Self::Nop => vec![],
         }
     }
 }
@@ -271,12 +781,31 @@ fn iter_once_if<T>(item: T, condition: bool) -> impl Iterator<Item = T> {
 
 impl<T: Buildable> Buildable for Vec<T> {
     fn build(self) -> Vec<InterpreterAction> {
-        self.into_iter().flat_map(Buildable::build).collect()
+        build_all(self)
+    }
+}
+
+impl Buildable for &[Instruction] {
+    fn build(self) -> Vec<InterpreterAction> {
+        self.iter().copied().map(InterpreterAction::Instruction).collect()
+    }
+}
+
+impl<T: Buildable, const N: usize> Buildable for [T; N] {
+    fn build(self) -> Vec<InterpreterAction> {
+        build_all(self)
     }
 }
 
+/// Builds each of `items` in turn, without needing to collect them into a `Vec` first.
+pub fn build_all(items: impl IntoIterator<Item = impl Buildable>) -> Vec<InterpreterAction> {
+    items.into_iter().flat_map(Buildable::build).collect()
+}
+
 pub fn offset_to_insns(offset: isize) -> Item {
-    if offset >= 0 {
+    if offset == 0 {
+        Item::Nop
+    } else if offset > 0 {
         Item::repeat(Instruction::Right.into(), offset.unsigned_abs())
     } else {
         Item::repeat(Instruction::Left.into(), offset.unsigned_abs())
@@ -298,3 +827,108 @@ pub fn zero_cell() -> Item {
 pub fn zero_cell_up() -> Item {
     Loop::new(vec![Instruction::Inc.into()]).into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{run_on, run_with_output};
+
+    // `depth` sequences inside each other (with some loops and repeats along the way), around a
+    // single instruction
+    fn nested(depth: usize) -> Item {
+        let mut item = Item::from(Instruction::Inc);
+        for i in 0..depth {
+            item = match i % 50 {
+                0 => item.repeat(2),
+                n if n % 5 == 0 => Loop::new(vec![item, Item::Nop]).into(),
+                _ => Item::Sequence(vec![Instruction::Right.into(), item, Instruction::Left.into()]),
+            };
+        }
+        item
+    }
+
+    #[test]
+    fn flatten_builds_the_same() {
+        let item = nested(200);
+        assert_eq!(item.clone().flatten().build(), item.build());
+    }
+
+    #[test]
+    fn flatten_deep_nesting() {
+        // Deep enough to overflow the stack if flattening recursed, so only sequences, which don't
+        // need building (or dropping) recursively once flattened
+        let mut item = Item::from(Instruction::Inc);
+        for _ in 0..1_000_000 {
+            item = Item::Sequence(vec![item]);
+        }
+        assert_eq!(item.flatten(), Item::Sequence(vec![Instruction::Inc.into()]));
+    }
+
+    #[test]
+    fn marker_misuse_is_an_error() {
+        let err = run_on(&[0, 0], Item::Sequence(vec![Item::add_marker("a"), Instruction::Right.into(), Item::add_marker("a")])).unwrap_err();
+        assert!(err.to_string().contains("marker \"a\" already exists"), "{err}");
+        let err = run_on(&[0], Item::remove_marker("a")).unwrap_err();
+        assert!(err.to_string().contains("marker \"a\" does not exist"), "{err}");
+        run_on(&[0], Item::Sequence(vec![Item::add_marker("a"), Item::remove_marker("a"), Item::add_marker("a")])).unwrap();
+    }
+
+    #[test]
+    fn emit_str() {
+        for text in ["", "a", "\n", "Hi!\n", "Total IP-level data: ", "~ \x01z"] {
+            let (tape, output) = run_with_output(&[0, 0], Item::emit_str(text)).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), text);
+            assert_eq!(tape, [0, 0], "scratch is left empty after {text:?}");
+        }
+        // Balanced, so the pointer is back on the current cell
+        assert_eq!(Item::emit_str("abc").check_balance().unwrap(), Some(0));
+    }
+
+    #[test]
+    fn zero_offset_is_nop() {
+        assert_eq!(offset_to_insns(0), Item::Nop);
+    }
+
+    #[test]
+    fn drain_mixed_signs() {
+        // Each offset is from the previous target, so these cross back over the origin
+        let tape = run_on(&[0, 0, 5, 0, 0], Item::Sequence(vec![offset_to_insns(2), drain(&[2, -4], true)])).unwrap();
+        assert_eq!(tape, [5, 0, 0, 0, 5]);
+        let tape = run_on(&[9, 9, 5, 9], Item::Sequence(vec![offset_to_insns(2), drain(&[-2, 3, -2], false)])).unwrap();
+        assert_eq!(tape, [4, 4, 0, 4]);
+    }
+
+    #[test]
+    fn drain_scaled_multiplies() {
+        let tape = run_on(&[5, 0], drain_scaled(&[(1, 3)], true)).unwrap();
+        assert_eq!(tape, [0, 15]);
+        let tape = run_on(&[5, 20, 11], drain_scaled(&[(2, 2), (-1, 3)], false)).unwrap();
+        assert_eq!(tape, [0, 5, 1]);
+        let tape = run_on(&[5, 7], drain_scaled(&[(1, 0)], true)).unwrap();
+        assert_eq!(tape, [0, 7]);
+    }
+
+    #[test]
+    fn counted_loop_runs_count_times() {
+        for count in [0, 1, 3, 255] {
+            // Prints how many times the body has run so far, each time it runs
+            let (tape, output) = run_with_output(&[0, 0], Item::counted_loop(count, bf!(">+.<"))).unwrap();
+            assert_eq!(tape, [0, count], "count {count}");
+            assert_eq!(output, (1..=count).collect::<Vec<_>>(), "count {count}");
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "return to the drained cell")]
+    fn drain_back_onto_origin() {
+        drain(&[1, -1], true);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "return to the drained cell")]
+    fn drain_zero_offset() {
+        drain(&[0], true);
+    }
+}
@@ -1,9 +1,23 @@
+use tap::Conv;
+
 use crate::{
-    build::{offset_to_insns, zero_cell, Item, Loop},
-    Instruction,
+    build::{drain, move_cell, offset_from, offset_to_insns, zero_cell, Item, Loop},
+    CommentLevel, Instruction,
 };
 
+/// An in-place operation on a `WIDTH`-cell number, run by [`operate`].
 pub trait NumericOperation {
+    const NAME: &'static str;
+    const WIDTH: usize;
+
+    /// Called on the last cell of the number, and returns there, with the two scratch cells at
+    /// `scratch_offset` zeroed beforehand and left zeroed.
+    fn apply(scratch_offset: isize) -> Item;
+}
+
+/// A [`NumericOperation`] that changes the last cell by one, carrying into (or borrowing from) the
+/// cells before it when `zero_reset` wraps a cell around.
+pub trait CarryingOperation {
     const NAME: &'static str;
     const ZERO_CHECK_FIRST: bool;
     const WIDTH: usize;
@@ -12,7 +26,16 @@ pub trait NumericOperation {
     fn zero_reset() -> Item;
 }
 
-fn operate_level<N: NumericOperation>(space: usize, scratch_offset: isize) -> Item {
+impl<N: CarryingOperation> NumericOperation for N {
+    const NAME: &'static str = N::NAME;
+    const WIDTH: usize = N::WIDTH;
+
+    fn apply(scratch_offset: isize) -> Item {
+        operate_level::<N>(N::WIDTH - 1, scratch_offset)
+    }
+}
+
+fn operate_level<N: CarryingOperation>(space: usize, scratch_offset: isize) -> Item {
     let marker_name = format!("operation {} level {}", N::NAME, N::WIDTH - space);
 
     let prep = Item::Sequence(vec![
@@ -30,6 +53,7 @@ fn operate_level<N: NumericOperation>(space: usize, scratch_offset: isize) -> It
             offset_to_insns(-scratch_offset),
             Instruction::Right.into(),
         ])
+        .unbalanced()
         .indent()
         .into(),
         offset_to_insns(scratch_offset),
@@ -56,7 +80,7 @@ fn operate_level<N: NumericOperation>(space: usize, scratch_offset: isize) -> It
         offset_to_insns(-scratch_offset - 1),
     ]);
 
-    let comment = Item::Comment(format!("{} {{depth={}/{}}}", N::NAME, N::WIDTH - space, N::WIDTH), 10);
+    let comment = Item::Comment(format!("{} {{depth={}/{}}}", N::NAME, N::WIDTH - space, N::WIDTH), CommentLevel::TRACE);
 
     let v = if N::ZERO_CHECK_FIRST {
         vec![
@@ -90,9 +114,28 @@ fn operate_level<N: NumericOperation>(space: usize, scratch_offset: isize) -> It
 }
 
 // `tape + scratch_offset` must be two scratch cells
+//
+// The cell just past the most significant digit must be zero, which debug builds check before and
+// after, along with the scratch cells being left empty, to catch a `WIDTH` that doesn't match the
+// number's.
+#[track_caller]
 pub fn operate<N: NumericOperation>(scratch_offset: isize) -> Item {
     let marker_name = format!("operation {}", N::NAME);
+    let boundary = -(N::WIDTH as isize);
+    let (before, after) = if cfg!(debug_assertions) {
+        (
+            Item::assert_zero(boundary, format!("{} {{width={}}} before", N::NAME, N::WIDTH)),
+            Item::Sequence(vec![
+                Item::assert_zero(boundary, format!("{} {{width={}}} after", N::NAME, N::WIDTH)),
+                Item::assert_zero(scratch_offset, format!("{} scratch", N::NAME)),
+                Item::assert_zero(scratch_offset + 1, format!("{} scratch", N::NAME)),
+            ]),
+        )
+    } else {
+        (Item::Nop, Item::Nop)
+    };
     Item::Sequence(vec![
+        before,
         Item::add_marker(marker_name.clone()),
         offset_to_insns(scratch_offset),
         zero_cell(),
@@ -100,16 +143,17 @@ pub fn operate<N: NumericOperation>(scratch_offset: isize) -> Item {
         zero_cell(),
         Instruction::Left.into(),
         offset_to_insns(-scratch_offset),
-        operate_level::<N>(N::WIDTH - 1, scratch_offset),
+        N::apply(scratch_offset),
         Item::assert_marker_offset(marker_name.clone(), 0, "after total operation"),
         Item::remove_marker(marker_name),
+        after,
     ])
 }
 
 pub struct ByteAdd<const N: usize>;
 pub struct ByteSub<const N: usize>;
 
-impl<const N: usize> NumericOperation for ByteAdd<N> {
+impl<const N: usize> CarryingOperation for ByteAdd<N> {
     const NAME: &'static str = "add";
     const ZERO_CHECK_FIRST: bool = false;
     const WIDTH: usize = N;
@@ -119,11 +163,11 @@ impl<const N: usize> NumericOperation for ByteAdd<N> {
     }
 
     fn zero_reset() -> Item {
-        Item::Sequence(vec![])
+        Item::Nop
     }
 }
 
-impl<const N: usize> NumericOperation for ByteSub<N> {
+impl<const N: usize> CarryingOperation for ByteSub<N> {
     const NAME: &'static str = "sub";
     const ZERO_CHECK_FIRST: bool = true;
     const WIDTH: usize = N;
@@ -133,13 +177,13 @@ impl<const N: usize> NumericOperation for ByteSub<N> {
     }
 
     fn zero_reset() -> Item {
-        Item::Sequence(vec![])
+        Item::Nop
     }
 }
 
 pub struct DecimalAdd<const N: usize>;
 
-impl<const N: usize> NumericOperation for DecimalAdd<N> {
+impl<const N: usize> CarryingOperation for DecimalAdd<N> {
     const NAME: &'static str = "decimal add";
     const ZERO_CHECK_FIRST: bool = false;
     const WIDTH: usize = N;
@@ -155,7 +199,7 @@ impl<const N: usize> NumericOperation for DecimalAdd<N> {
 
 pub struct DecimalSub<const N: usize>;
 
-impl<const N: usize> NumericOperation for DecimalSub<N> {
+impl<const N: usize> CarryingOperation for DecimalSub<N> {
     const NAME: &'static str = "decimal sub";
     const ZERO_CHECK_FIRST: bool = true;
     const WIDTH: usize = N;
@@ -168,3 +212,639 @@ impl<const N: usize> NumericOperation for DecimalSub<N> {
         Item::Sequence(vec![Instruction::Inc.into(); 10])
     }
 }
+
+fn new_zero_check(temp_copy: isize, accumulator: isize) -> Item {
+    Item::Sequence(vec![
+        Loop::new(vec![
+            Instruction::Dec.into(),
+            offset_to_insns(temp_copy),
+            Instruction::Inc.into(),
+            offset_to_insns(-temp_copy),
+            offset_to_insns(accumulator),
+            Instruction::Inc.into(),
+            offset_to_insns(-accumulator),
+        ])
+        .into(),
+        offset_to_insns(temp_copy),
+        drain(&[-temp_copy], true),
+        offset_to_insns(-temp_copy),
+    ])
+}
+
+// On the last cell of the number, sets `accumulator` to nonzero iff the number is nonzero
+// `temp_copy` must be a zero cell, and is restored to zero
+fn zero_check_number(width: usize, temp_copy: isize, accumulator: isize) -> Item {
+    let s = (0..width)
+        .flat_map(|i| [new_zero_check(temp_copy + i as isize, accumulator + i as isize), Instruction::Left.into()])
+        .collect();
+
+    Item::Sequence(vec![
+        offset_to_insns(accumulator),
+        zero_cell(),
+        offset_to_insns(-accumulator),
+        Item::Sequence(s),
+        offset_to_insns(width as _),
+    ])
+    .comment(format!("zero check number {{width={width}}}"), CommentLevel::DEBUG)
+}
+
+/// Long division of an `N`-digit decimal number by a `D`-digit one, by repeated subtraction.
+///
+/// Called on cell `0` of the following layout, where each number is stored most significant
+/// digit first and the associated constants give the offset of its *last* digit:
+/// - cells `0` and `1` are scratch
+/// - [`Self::DIVIDEND`] is followed by a zero cell, and is consumed by the division
+/// - [`Self::DIVISOR`] is followed by a zero cell, and is left as `divisor - remainder`
+/// - [`Self::REMAINDER`] is followed by a zero cell, and must start out zero
+/// - [`Self::QUOTIENT`] is followed by a zero cell, and must start out zero
+///
+/// The dividend and divisor are in the face-value form used by [`DecimalSub`]. The quotient is
+/// left in face-value form, whereas the remainder is left in the offset form used by
+/// [`DecimalAdd`] (i.e. each digit is stored as `digit - 10`).
+pub struct DecimalDivide<const N: usize, const D: usize>;
+
+impl<const N: usize, const D: usize> DecimalDivide<N, D> {
+    const ZC: usize = 0;
+    const SC: usize = 1;
+
+    pub const DIVIDEND: usize = Self::SC + 2 + N - 1;
+    pub const DIVISOR: usize = Self::DIVIDEND + 1 + D;
+    pub const REMAINDER: usize = Self::DIVISOR + 1 + D;
+    pub const QUOTIENT: usize = Self::REMAINDER + 1 + N;
+
+    pub fn divide() -> Item {
+        const MARK: &str = "decimal divide";
+
+        fn at(cell: usize, message: &str) -> Item {
+            Item::assert_marker_offset(MARK, cell as _, message)
+        }
+
+        let zc = Self::ZC;
+        let sc = Self::SC;
+        let n = Self::DIVIDEND;
+        let n0 = n + 1;
+        let d = Self::DIVISOR;
+        let t = Self::REMAINDER;
+        let t0 = t + 1;
+        let q = Self::QUOTIENT;
+        let q0 = q + 1;
+
+        Item::Sequence(vec![
+            Item::add_marker(MARK),
+            offset_to_insns(offset_from(0, t0)),
+            Instruction::Inc.conv::<Item>().repeat(10),
+            Loop::new(vec![
+                Instruction::Dec.into(),
+                Item::Sequence(vec![Instruction::Left.into(), Instruction::Dec.into()]).repeat(D),
+                Instruction::Right.conv::<Item>().repeat(D),
+            ])
+            .into(),
+            at(t0, "after init"),
+            offset_to_insns(offset_from(t0, 0)),
+            offset_to_insns(offset_from(0, q0)),
+            Instruction::Inc.conv::<Item>().repeat(10),
+            Loop::new(vec![
+                Instruction::Dec.into(),
+                Item::Sequence(vec![Instruction::Left.into(), Instruction::Dec.into()]).repeat(N),
+                offset_to_insns(N as _),
+            ])
+            .into(),
+            at(q0, "Q setup"),
+            offset_to_insns(offset_from(q0, 0)),
+            // Setup complete, at cell 0
+            offset_to_insns(offset_from(0, n)),
+            zero_check_number(N, offset_from(n, sc), offset_from(n, zc)),
+            at(n, "still here"),
+            offset_to_insns(offset_from(n, zc)),
+            Loop::new(vec![
+                zero_cell(),
+                offset_to_insns(offset_from(zc, n)),
+                operate::<DecimalSub<N>>(offset_from(n, zc)),
+                at(n, "after N subtract"),
+                offset_to_insns(offset_from(n, zc)),
+                zero_cell(),
+                offset_to_insns(offset_from(zc, d)),
+                operate::<DecimalSub<D>>(offset_from(d, zc)),
+                at(d, "after D subtract"),
+                zero_check_number(D, offset_from(d, sc), offset_from(d, zc)),
+                offset_to_insns(offset_from(d, zc)),
+                drain(&[offset_from(zc, n0)], true),
+                offset_to_insns(offset_from(zc, t)),
+                operate::<DecimalAdd<D>>(offset_from(t, zc)),
+                at(t, "after T add"),
+                offset_to_insns(offset_from(t, n0)),
+                drain(&[offset_from(n0, zc)], true),
+                offset_to_insns(offset_from(n0, zc)),
+                Instruction::Right.into(),
+                zero_cell(),
+                Instruction::Inc.into(),
+                Instruction::Left.into(),
+                // If nonzero (i.e. d != 0)
+                Loop::new(vec![
+                    zero_cell(),
+                    Instruction::Right.into(),
+                    zero_cell(),
+                    Instruction::Left.into(),
+                ])
+                .into(),
+                Instruction::Right.into(),
+                at(zc + 1, "before else"),
+                // Else (i.e. d == 0)
+                Loop::new(vec![
+                    zero_cell(),
+                    offset_to_insns(offset_from(zc + 1, t)),
+                    Item::Sequence(vec![drain(&[offset_from(t, d)], true), Instruction::Left.into()]).repeat(D),
+                    at(d + 1, "after restore D"),
+                    offset_to_insns(offset_from(d + 1, t0)),
+                    Instruction::Inc.conv::<Item>().repeat(10),
+                    Loop::new(vec![
+                        Instruction::Dec.into(),
+                        Item::Sequence(vec![Instruction::Left.into(), Instruction::Dec.into()]).repeat(D),
+                        Instruction::Left.into(),
+                        Item::Sequence(vec![Instruction::Left.into(), Instruction::Inc.into()]).repeat(D),
+                        Instruction::Right.conv::<Item>().repeat(D + D + 1),
+                    ])
+                    .into(),
+                    at(t0, "after unreset T+D"),
+                    offset_to_insns(offset_from(t0, q)),
+                    operate::<DecimalAdd<N>>(offset_from(q, zc)),
+                    at(q, "after increment Q"),
+                    offset_to_insns(offset_from(q, zc + 1)),
+                ])
+                .into(),
+                offset_to_insns(offset_from(zc + 1, n)),
+                zero_check_number(N, offset_from(n, sc), offset_from(n, zc)),
+                at(n, "before loop"),
+                offset_to_insns(offset_from(n, zc)),
+            ])
+            .into(),
+            offset_to_insns(offset_from(zc, q0)),
+            Instruction::Inc.conv::<Item>().repeat(10),
+            Loop::new(vec![
+                Instruction::Dec.into(),
+                Item::Sequence(vec![Instruction::Left.into(), Instruction::Inc.into()]).repeat(N),
+                offset_to_insns(N as _),
+            ])
+            .into(),
+            at(q0, "Q desetup"),
+            offset_to_insns(offset_from(q0, 0)),
+            Item::remove_marker(MARK),
+        ])
+        .comment(format!("decimal divide {{width={N}/{D}}}"), CommentLevel::DEBUG)
+    }
+}
+
+/// As [`DecimalDivide`] (with the same layout), but also converts the remainder to face-value
+/// form, for when the remainder is wanted rather than (or as well as) the quotient.
+pub struct DecimalMod<const N: usize, const D: usize>;
+
+impl<const N: usize, const D: usize> DecimalMod<N, D> {
+    pub const DIVIDEND: usize = DecimalDivide::<N, D>::DIVIDEND;
+    pub const DIVISOR: usize = DecimalDivide::<N, D>::DIVISOR;
+    pub const REMAINDER: usize = DecimalDivide::<N, D>::REMAINDER;
+    pub const QUOTIENT: usize = DecimalDivide::<N, D>::QUOTIENT;
+
+    pub fn modulo() -> Item {
+        Item::Sequence(vec![
+            DecimalDivide::<N, D>::divide(),
+            offset_to_insns(offset_from(0, Self::REMAINDER)),
+            Item::Sequence(vec![Instruction::Inc.conv::<Item>().repeat(10), Instruction::Left.into()]).repeat(D),
+            offset_to_insns(offset_from(Self::REMAINDER - D, 0)),
+        ])
+        .comment(format!("decimal modulo {{width={N}/{D}}}"), CommentLevel::DEBUG)
+    }
+}
+
+/// Compares two `W`-digit decimal numbers, `a` and `b`, by repeatedly decrementing both.
+///
+/// Called on cell `0` of the following layout, where each number is stored most significant
+/// digit first (in the face-value form used by [`DecimalSub`]) and the associated constants give
+/// the offset of its *last* digit:
+/// - cells `0` and `1` are scratch, and cell `2` must be zero
+/// - [`Self::A`] is followed by a zero cell
+/// - [`Self::B`] is followed by a zero cell
+/// - [`Self::GREATER`], [`Self::LESS`] and the cell after them must start out zero
+///
+/// Afterwards, [`Self::GREATER`] is 1 iff `a > b` and [`Self::LESS`] is 1 iff `a < b` (so both
+/// are 0 iff `a == b`). Both numbers have `min(a, b)` subtracted from them.
+///
+/// With a width of 1 nothing is ever borrowed, so it compares any two cells, not just digits.
+pub struct DecimalCompare<const W: usize>;
+
+impl<const W: usize> DecimalCompare<W> {
+    const ZC: usize = 0;
+    const SC: usize = 1;
+
+    pub const A: usize = Self::SC + 2 + W - 1;
+    pub const B: usize = Self::A + 1 + W;
+    pub const GREATER: usize = Self::B + 2;
+    pub const LESS: usize = Self::GREATER + 1;
+    const BOTH: usize = Self::LESS + 1;
+
+    pub fn compare() -> Item {
+        const MARK: &str = "decimal compare";
+
+        // From cell 0, sets `flag` to 1 iff the number ending at `number` is nonzero
+        fn nonzero_flag<const W: usize>(number: usize, flag: usize) -> Item {
+            let zc = DecimalCompare::<W>::ZC;
+            let sc = DecimalCompare::<W>::SC;
+            Item::Sequence(vec![
+                offset_to_insns(offset_from(0, sc)),
+                zero_cell(),
+                offset_to_insns(offset_from(sc, number)),
+                zero_check_number(W, offset_from(number, sc), offset_from(number, zc)),
+                offset_to_insns(offset_from(number, zc)),
+                Loop::new(vec![
+                    zero_cell(),
+                    offset_to_insns(offset_from(zc, flag)),
+                    Instruction::Inc.into(),
+                    offset_to_insns(offset_from(flag, zc)),
+                ])
+                .into(),
+                offset_to_insns(offset_from(zc, 0)),
+            ])
+        }
+
+        // From cell 0, sets `BOTH` to 1 iff both numbers are nonzero, leaving the pointer there
+        fn both_nonzero<const W: usize>() -> Item {
+            let greater = DecimalCompare::<W>::GREATER;
+            let less = DecimalCompare::<W>::LESS;
+            let both = DecimalCompare::<W>::BOTH;
+            Item::Sequence(vec![
+                nonzero_flag::<W>(DecimalCompare::<W>::A, greater),
+                nonzero_flag::<W>(DecimalCompare::<W>::B, less),
+                offset_to_insns(offset_from(0, greater)),
+                Loop::new(vec![
+                    zero_cell(),
+                    offset_to_insns(offset_from(greater, less)),
+                    Loop::new(vec![
+                        zero_cell(),
+                        offset_to_insns(offset_from(less, both)),
+                        Instruction::Inc.into(),
+                        offset_to_insns(offset_from(both, less)),
+                    ])
+                    .into(),
+                    offset_to_insns(offset_from(less, greater)),
+                ])
+                .into(),
+                offset_to_insns(offset_from(greater, less)),
+                zero_cell(),
+                offset_to_insns(offset_from(less, both)),
+            ])
+        }
+
+        let zc = Self::ZC;
+        let a = Self::A;
+        let b = Self::B;
+        let both = Self::BOTH;
+
+        Item::Sequence(vec![
+            Item::add_marker(MARK),
+            both_nonzero::<W>(),
+            Loop::new(vec![
+                zero_cell(),
+                offset_to_insns(offset_from(both, a)),
+                operate::<DecimalSub<W>>(offset_from(a, zc)),
+                Item::assert_marker_offset(MARK, a as _, "after A subtract"),
+                offset_to_insns(offset_from(a, b)),
+                operate::<DecimalSub<W>>(offset_from(b, zc)),
+                Item::assert_marker_offset(MARK, b as _, "after B subtract"),
+                offset_to_insns(offset_from(b, 0)),
+                both_nonzero::<W>(),
+            ])
+            .into(),
+            Item::assert_marker_offset(MARK, both as _, "after decrement loop"),
+            offset_to_insns(offset_from(both, 0)),
+            // At most one of the two is now nonzero
+            nonzero_flag::<W>(a, Self::GREATER),
+            nonzero_flag::<W>(b, Self::LESS),
+            Item::assert_marker_offset(MARK, 0, "after compare"),
+            Item::remove_marker(MARK),
+        ])
+        .comment(format!("decimal compare {{width={W}}}"), CommentLevel::DEBUG)
+    }
+}
+
+/// Replaces an `N`-digit decimal number `x` (in the face-value form used by [`DecimalSub`]) with
+/// its nines' complement `10^N - 1 - x` in place, i.e. each digit `d` becomes `9 - d`, when run
+/// with [`operate`]. Only the first of the scratch cells is used.
+pub struct DecimalComplement<const N: usize>;
+
+impl<const N: usize> NumericOperation for DecimalComplement<N> {
+    const NAME: &'static str = "decimal complement";
+    const WIDTH: usize = N;
+
+    fn apply(scratch_offset: isize) -> Item {
+        let digits = (0..N as isize)
+            .flat_map(|i| {
+                // The same cell for every digit, as the pointer moves left along the number
+                let scratch = scratch_offset + i;
+                [
+                    drain(&[scratch], true),
+                    Instruction::Inc.conv::<Item>().repeat(9),
+                    offset_to_insns(scratch),
+                    drain(&[-scratch], false),
+                    offset_to_insns(-scratch),
+                    Instruction::Left.into(),
+                ]
+            })
+            .collect();
+
+        Item::Sequence(vec![Item::Sequence(digits), offset_to_insns(N as _)])
+            .comment(format!("decimal complement {{width={N}}}"), CommentLevel::DEBUG)
+    }
+}
+
+/// Prints a `value_cells`-digit decimal number (in the face-value form used by [`DecimalSub`])
+/// without leading zeros, printing a lone `0` if every digit is zero.
+///
+/// Called on the most significant digit, and returns there with the number unchanged. A single
+/// cell is printed as a plain byte (so `0..=255`, which includes a single digit), with the 10
+/// cells to its right as scratch. For wider numbers, the cell to the left and the
+/// `2 * value_cells + 1` cells to the right of the number are scratch. In both cases the scratch
+/// cells must start out zero, and are left zeroed.
+pub fn write_number(value_cells: usize) -> Item {
+    if value_cells == 1 {
+        // Taken from https://esolangs.org/wiki/Brainfuck_algorithms#Print_value_of_cell_x_as_number_(8-bit)
+        return crate::bf!(
+            ">>++++++++++<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]>>[-]>>>++++++++++<[->-[>+>>]>\
+            [+[-<+>]>+>>]<<<<<]>[-]>>[>++++++[-<++++++++>]<.<<+>+>[-]]<[<[->-<]++++++[->++++++++<]>.[-]\
+            ]<<++++++[-<++++++++>]<.[-]<<[-<+>]<"
+        );
+    }
+
+    let width = value_cells;
+    let mark = "write number";
+    Item::Sequence(vec![
+        Item::add_marker(mark),
+        offset_to_insns(2 * width as isize),
+        Instruction::Right.into(),
+        Instruction::Inc.conv::<Item>().repeat(8),
+        Loop::new(vec![
+            Instruction::Dec.into(),
+            Instruction::Left.into(),
+            Instruction::Inc.conv::<Item>().repeat(6),
+            Instruction::Right.into(),
+        ])
+        .into(),
+        Instruction::Left.into(),
+        Loop::new(vec![
+            Instruction::Dec.into(),
+            crate::bf!(">+").repeat(width),
+            Instruction::Left.conv::<Item>().repeat(width),
+        ])
+        .into(),
+        Item::assert_marker_offset(mark, 2 * width as isize, "init output end"),
+        Instruction::Dec.into(),
+        // The last digit is always printed, so that zero is printed as `0` rather than nothing
+        Instruction::Left.into(),
+        Instruction::Inc.into(),
+        offset_to_insns(offset_from(2 * width - 1, width - 1)),
+        Item::Sequence(vec![
+            Loop::new(vec![
+                Instruction::Dec.into(),
+                offset_to_insns(width as isize),
+                zero_cell(),
+                Instruction::Inc.into(),
+                offset_to_insns(width as isize + 1),
+                Instruction::Inc.into(),
+                offset_to_insns(-(2 * width as isize + 1)),
+            ])
+            .into(),
+            Instruction::Left.into(),
+        ])
+        .repeat(width)
+        .comment("leading zeros filter", CommentLevel::DEBUG),
+        Item::assert_marker_offset(mark, -1, "after leading zeros"),
+        find_non_zero_cell_right(),
+        Instruction::Left.into(),
+        Instruction::Inc.into(),
+        Instruction::Right.into(),
+        Instruction::Inc.into(),
+        Loop::new(vec![
+            Loop::new(vec![
+                zero_cell(),
+                offset_to_insns(width as isize + 1),
+                Instruction::Output.into(),
+                offset_to_insns(-(width as isize + 1)),
+            ])
+            .into(),
+            Instruction::Right.into(),
+            Instruction::Inc.into(),
+        ])
+        .unbalanced()
+        .into(),
+        Item::Sequence(vec![
+            Item::Sequence(vec![Instruction::Left.into(), zero_cell()]).repeat(width + 1),
+            find_non_zero_cell_right(),
+            Item::assert_marker_offset(mark, 2 * width as isize + 1, "begin restore number"),
+            Instruction::Left.conv::<Item>().repeat(2),
+            Instruction::Inc.conv::<Item>().repeat(8),
+            Loop::new(vec![
+                Instruction::Dec.into(),
+                Instruction::Right.into(),
+                Instruction::Inc.conv::<Item>().repeat(6),
+                Instruction::Left.into(),
+            ])
+            .into(),
+            Instruction::Right.into(),
+            Loop::new(vec![
+                Instruction::Dec.into(),
+                crate::bf!(">-").repeat(width),
+                Instruction::Left.conv::<Item>().repeat(width),
+            ])
+            .into(),
+            Instruction::Left.conv::<Item>().repeat(2 * width),
+        ])
+        .comment("decimal cleanup", CommentLevel::DEBUG),
+        Item::assert_marker_offset(mark, 0, "decimal reset"),
+        // The digits were restored past the scratch, so move them back
+        Item::Sequence(vec![
+            offset_to_insns(2 * width as isize + 1),
+            Item::Sequence(vec![move_cell(-(2 * width as isize + 1)), Instruction::Right.into()]).repeat(width),
+            offset_to_insns(-(3 * width as isize + 1)),
+        ])
+        .comment("move number back", CommentLevel::DEBUG),
+        Item::assert_marker_offset(mark, 0, "number moved back"),
+        Item::remove_marker(mark),
+    ])
+    .comment(format!("write number {{width={width}}}"), CommentLevel::INFO)
+}
+
+fn find_non_zero_cell_right() -> Item {
+    // "Find a non-zeroed cell" from https://esolangs.org/wiki/Brainfuck_algorithms
+    crate::bf!("+[>[<-]<[->+<]>]>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{run_on, run_with_output};
+
+    // The `width` digits of the number whose last digit is at `end`
+    fn digits(tape: &[u8], end: usize, width: usize) -> &[u8] {
+        &tape[end + 1 - width..=end]
+    }
+
+    // The tape for a `DecimalDivide<N, D>`, in face-value form
+    fn division_tape<const N: usize, const D: usize>(dividend: [u8; N], divisor: [u8; D]) -> Vec<u8> {
+        let mut tape = vec![0; DecimalDivide::<N, D>::QUOTIENT + 2];
+        tape[DecimalDivide::<N, D>::DIVIDEND + 1 - N..=DecimalDivide::<N, D>::DIVIDEND].copy_from_slice(&dividend);
+        tape[DecimalDivide::<N, D>::DIVISOR + 1 - D..=DecimalDivide::<N, D>::DIVISOR].copy_from_slice(&divisor);
+        tape
+    }
+
+    #[test]
+    fn divide() {
+        type Divide = DecimalDivide<3, 2>;
+        let cases = [
+            // dividend, divisor, quotient, remainder
+            ([1, 2, 3], [1, 2], [0, 1, 0], [0, 3]),
+            ([1, 0, 0], [2, 5], [0, 0, 4], [0, 0]),
+            ([9, 9, 9], [0, 7], [1, 4, 2], [0, 5]),
+            ([0, 0, 5], [1, 0], [0, 0, 0], [0, 5]),
+            ([0, 0, 0], [0, 3], [0, 0, 0], [0, 0]),
+        ];
+        for (dividend, divisor, quotient, remainder) in cases {
+            let tape = run_on(&division_tape(dividend, divisor), Divide::divide()).unwrap();
+            assert_eq!(digits(&tape, Divide::QUOTIENT, 3), quotient, "{dividend:?} / {divisor:?}");
+            // Left in `DecimalAdd`'s offset form
+            let offset = remainder.map(|digit: u8| digit.wrapping_sub(10));
+            assert_eq!(digits(&tape, Divide::REMAINDER, 2), offset, "{dividend:?} % {divisor:?}");
+            assert_eq!(digits(&tape, Divide::DIVIDEND, 3), [0; 3], "dividend is consumed");
+            assert_eq!(tape[..2], [0, 0], "scratch is left empty");
+        }
+    }
+
+    #[test]
+    fn modulo() {
+        type Mod = DecimalMod<3, 2>;
+        let cases = [
+            ([1, 2, 3], [1, 2], [0, 1, 0], [0, 3]),
+            ([2, 5, 5], [1, 6], [0, 1, 5], [1, 5]),
+            ([1, 0, 0], [2, 5], [0, 0, 4], [0, 0]),
+            ([0, 4, 2], [4, 3], [0, 0, 0], [4, 2]),
+        ];
+        for (dividend, divisor, quotient, remainder) in cases {
+            let tape = run_on(&division_tape(dividend, divisor), Mod::modulo()).unwrap();
+            assert_eq!(digits(&tape, Mod::QUOTIENT, 3), quotient, "{dividend:?} / {divisor:?}");
+            assert_eq!(digits(&tape, Mod::REMAINDER, 2), remainder, "{dividend:?} % {divisor:?}");
+        }
+    }
+
+    // Runs a `DecimalCompare<W>` on `a` and `b`, returning `(GREATER, LESS)` and checking what's
+    // left of the numbers
+    fn compare<const W: usize>(a: [u8; W], b: [u8; W]) -> (u8, u8) {
+        let mut tape = vec![0; DecimalCompare::<W>::LESS + 2];
+        tape[DecimalCompare::<W>::A + 1 - W..=DecimalCompare::<W>::A].copy_from_slice(&a);
+        tape[DecimalCompare::<W>::B + 1 - W..=DecimalCompare::<W>::B].copy_from_slice(&b);
+        let tape = run_on(&tape, DecimalCompare::<W>::compare()).unwrap();
+
+        // Both have `min(a, b)` subtracted, so at most one is left nonzero
+        let left = [digits(&tape, DecimalCompare::<W>::A, W), digits(&tape, DecimalCompare::<W>::B, W)];
+        assert!(left.iter().any(|number| number.iter().all(|&digit| digit == 0)), "{a:?} vs {b:?}: {left:?}");
+        assert_eq!(tape[..3], [0; 3], "scratch is left empty");
+        assert_eq!(tape[DecimalCompare::<W>::LESS + 1], 0, "scratch is left empty");
+        (tape[DecimalCompare::<W>::GREATER], tape[DecimalCompare::<W>::LESS])
+    }
+
+    #[test]
+    fn compare_equal() {
+        assert_eq!(compare([0, 0, 0], [0, 0, 0]), (0, 0));
+        assert_eq!(compare([1, 0, 7], [1, 0, 7]), (0, 0));
+        assert_eq!(compare([200], [200]), (0, 0));
+    }
+
+    #[test]
+    fn compare_less() {
+        assert_eq!(compare([0, 0, 0], [0, 0, 1]), (0, 1));
+        assert_eq!(compare([0, 9, 9], [1, 0, 0]), (0, 1));
+        assert_eq!(compare([2, 1, 5], [2, 2, 0]), (0, 1));
+        assert_eq!(compare([12], [255]), (0, 1));
+    }
+
+    #[test]
+    fn compare_greater() {
+        assert_eq!(compare([0, 0, 1], [0, 0, 0]), (1, 0));
+        assert_eq!(compare([1, 0, 0], [0, 9, 9]), (1, 0));
+        assert_eq!(compare([3, 0, 4], [3, 0, 3]), (1, 0));
+        assert_eq!(compare([255], [0]), (1, 0));
+    }
+
+    #[test]
+    fn operate_matching_width() {
+        let tape = run_on(&[0, 1, 2, 0, 0, 0], Item::Sequence(vec![offset_to_insns(3), operate::<DecimalSub<3>>(1)])).unwrap();
+        assert_eq!(tape, [0, 1, 1, 9, 0, 0]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn operate_mismatched_width() {
+        // A 3-digit number operated on as if it were 2 digits wide
+        let err = run_on(&[0, 1, 2, 3, 0, 0], Item::Sequence(vec![offset_to_insns(3), operate::<DecimalSub<2>>(1)])).unwrap_err();
+        assert!(err.to_string().contains("decimal sub {width=2} before"), "{err}");
+    }
+
+    #[test]
+    fn complement() {
+        let cases: [(&[u8], &[u8]); 5] = [
+            (&[0], &[9]),
+            (&[4], &[5]),
+            (&[1, 2, 3], &[8, 7, 6]),
+            (&[0, 0, 0], &[9, 9, 9]),
+            (&[9, 0, 9, 0, 5], &[0, 9, 0, 9, 4]),
+        ];
+        for (number, expected) in cases {
+            let width = number.len();
+            // The zero cell before the number, the number, then the two scratch cells
+            let mut cells = vec![0];
+            cells.extend_from_slice(number);
+            cells.extend([0, 0]);
+            let item = |operation| Item::Sequence(vec![offset_to_insns(width as _), operation]);
+            let operation = match width {
+                1 => operate::<DecimalComplement<1>>(1),
+                3 => operate::<DecimalComplement<3>>(1),
+                5 => operate::<DecimalComplement<5>>(1),
+                _ => unreachable!(),
+            };
+            let tape = run_on(&cells, item(operation)).unwrap();
+            assert_eq!(digits(&tape, width, width), expected, "{number:?}");
+            assert_eq!(tape[0], 0, "{number:?}");
+            assert!(tape[width + 1..].iter().all(|&cell| cell == 0), "scratch is left empty");
+        }
+    }
+
+    #[test]
+    fn write_single_cell() {
+        for (value, text) in [(0, "0"), (7, "7"), (255, "255")] {
+            let (tape, output) = run_with_output(&[value], Item::Sequence(vec![write_number(1), Item::assert_position(0, "back on the number")])).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), text);
+            assert_eq!(tape[0], value, "left unchanged");
+            assert!(tape[1..].iter().all(|&cell| cell == 0), "scratch is left empty");
+        }
+    }
+
+    #[test]
+    fn write_multiple_cells() {
+        let cases: [(&[u8], &str); 6] = [
+            (&[0, 0, 0], "0"),
+            (&[0, 0, 7], "7"),
+            (&[0, 4, 2], "42"),
+            (&[1, 0, 0], "100"),
+            (&[9, 9, 9], "999"),
+            (&[0, 0, 0, 0, 0, 0, 0, 4, 9], "49"),
+        ];
+        for (digits, text) in cases {
+            let width = digits.len();
+            // The scratch cell to the left of the number, the number, then the scratch to its right
+            let mut cells = vec![0];
+            cells.extend_from_slice(digits);
+            cells.resize(1 + width + 2 * width + 1, 0);
+            let item = Item::Sequence(vec![Instruction::Right.into(), write_number(width), Item::assert_position(1, "back on the number")]);
+            let (tape, output) = run_with_output(&cells, item).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), text, "{digits:?}");
+            assert_eq!(tape[..1 + width], cells[..1 + width], "{digits:?} left unchanged");
+            assert!(tape[1 + width..].iter().all(|&cell| cell == 0), "scratch is left empty");
+        }
+    }
+}
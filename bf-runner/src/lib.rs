@@ -8,38 +8,338 @@ use std::{
 
 use anyhow::{anyhow, bail, Context};
 
-use crate::build::InterpreterAction;
+use crate::build::{Buildable, InterpreterAction, Item};
 
 pub mod build;
 
 pub struct Interpreter {
     input: Box<dyn Read>,
+    input_exhausted: bool,
+    output: Box<dyn Write>,
+    flush_policy: FlushPolicy,
+    // Output not yet written to `output`, for any policy but `FlushPolicy::EachByte`
+    output_buffer: Vec<u8>,
     program: Program,
     instruction_pointer: usize,
     tape_pointer: usize,
     tape: Vec<u8>,
-    printing_level: Option<u8>,
+    printing_level: Option<CommentLevel>,
     markers: HashMap<String, Marker>,
+    stripped: Option<StrippedProgram>,
+    tape_mode: TapeMode,
+    // Where cell 0 is on `tape`, only nonzero for `TapeMode::Bidirectional`
+    origin: usize,
+    overflow_check: bool,
+    max_cells: Option<usize>,
+    loop_iteration_warn: Option<u64>,
+    // How many times each running loop has jumped back since it was entered, by its `[`'s index
+    loop_iterations: HashMap<usize, u64>,
+    trace: Option<Box<TraceFn>>,
+    assertions: AssertionMode,
+    profile: Option<Profile>,
+}
+
+type TraceFn = dyn FnMut(&Instruction, usize, Tape<'_>);
+
+// For `Interpreter::run_recording`
+struct Recording<'w> {
+    every: usize,
+    executed: u64,
+    w: &'w mut dyn Write,
+}
+
+impl Recording<'_> {
+    fn record(&mut self, tape: Tape<'_>) -> std::io::Result<()> {
+        self.executed += 1;
+        if self.executed.is_multiple_of(self.every as u64) {
+            writeln!(self.w, "{:>10} {tape}", self.executed)?;
+        }
+        Ok(())
+    }
+}
+
+// Instruction counts for `Interpreter::section_profile`
+struct Profile {
+    // The index of each open section's `Comment`, innermost last
+    sections: Vec<usize>,
+    // How many sections were open on entering each loop that's currently running
+    loop_depths: Vec<usize>,
+    // By the index of the section's `Comment`
+    counts: Vec<u64>,
+}
+
+impl Profile {
+    fn new(program_length: usize) -> Self {
+        Self {
+            sections: vec![],
+            loop_depths: vec![],
+            counts: vec![0; program_length],
+        }
+    }
+
+    fn record(&mut self, instruction: Instruction, jump: bool) {
+        if let Some(&section) = self.sections.last() {
+            self.counts[section] += 1;
+        }
+        // As with `Item::check_balance`, a comment without an `EndComment` lasts until the end of
+        // the loop it's in
+        match instruction {
+            Instruction::Start if !jump => self.loop_depths.push(self.sections.len()),
+            Instruction::End => {
+                let depth = if jump { self.loop_depths.last().copied() } else { self.loop_depths.pop() };
+                self.sections.truncate(depth.unwrap_or(0));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// How important a comment is, only comments at or above the interpreter's
+/// [print level](Interpreter::set_print_level) are printed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct CommentLevel(pub u8);
+
+impl CommentLevel {
+    /// Comments in parsed source, which don't have a level of their own
+    pub const PARSED: Self = Self(0);
+    /// Inner details, e.g. each step of copying a number around
+    pub const TRACE: Self = Self(80);
+    /// Individual branches and checks
+    pub const DEBUG: Self = Self(120);
+    /// Per-packet control flow
+    pub const INFO: Self = Self(160);
+    /// Major stages of the program
+    pub const SECTION: Self = Self(200);
+}
+
+impl From<u8> for CommentLevel {
+    fn from(level: u8) -> Self {
+        Self(level)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum TapeMode {
+    /// Grows rightwards as needed, moving left of cell 0 is a panic
+    #[default]
+    Unbounded,
+    /// A fixed number of cells, with the pointer wrapping around at both ends
+    Circular(usize),
+    /// Grows in both directions, so cells left of the starting cell can be used
+    ///
+    /// Growing leftwards shifts the whole tape (and any markers) along, so is slower than
+    /// [`TapeMode::Unbounded`].
+    Bidirectional,
+}
+
+/// What happens when a custom action, e.g. [`Item::assert_position`], fails.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum AssertionMode {
+    /// Prints the failure (which usually includes the tape) and exits the process, for debugging
+    /// the generator
+    #[default]
+    Fatal,
+    /// Stops the program and returns the failure from [`Interpreter::run`]
+    Error,
+}
+
+/// When the program's output is written out and flushed, see [`Interpreter::set_flush_policy`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum FlushPolicy {
+    /// After every byte, so output is seen immediately even if the program is interactive
+    #[default]
+    EachByte,
+    /// After every `\n`, and when the program finishes
+    OnNewline,
+    /// Only when the program finishes, which is fastest for programs that print a lot
+    OnEnd,
 }
 
 impl Interpreter {
     pub fn new(program: Program, input: impl Read + 'static) -> Self {
+        Self::with_tape_capacity(program, input, 1)
+    }
+
+    /// As [`Interpreter::new`], but reserves space for `cells` tape cells up front.
+    ///
+    /// The tape still only grows as the program moves right (so e.g. [`Tape`]'s `Display` is
+    /// unaffected), this just avoids reallocating for programs known to use a large tape.
+    pub fn with_tape_capacity(program: Program, input: impl Read + 'static, cells: usize) -> Self {
+        let mut tape = Vec::with_capacity(cells.max(1));
+        tape.push(0);
         Self {
             input: Box::new(input),
+            input_exhausted: false,
+            output: Box::new(std::io::stdout()),
+            flush_policy: FlushPolicy::EachByte,
+            output_buffer: vec![],
             program,
             instruction_pointer: 0,
             tape_pointer: 0,
-            tape: vec![0],
+            tape,
             printing_level: None,
             markers: Default::default(),
+            stripped: None,
+            tape_mode: TapeMode::Unbounded,
+            origin: 0,
+            overflow_check: false,
+            max_cells: None,
+            loop_iteration_warn: None,
+            loop_iterations: HashMap::new(),
+            trace: None,
+            assertions: AssertionMode::Fatal,
+            profile: None,
         }
     }
 
-    pub fn set_print_level(&mut self, level: u8) {
-        self.printing_level = Some(level);
+    /// Returns the interpreter to its initial state with new input, so the same program can be
+    /// run again without rebuilding it. The print level and tape mode are kept.
+    pub fn reset(&mut self, input: impl Read + 'static) {
+        self.input = Box::new(input);
+        self.input_exhausted = false;
+        self.instruction_pointer = 0;
+        self.tape_pointer = 0;
+        match self.tape_mode {
+            TapeMode::Unbounded | TapeMode::Bidirectional => {
+                self.tape.clear();
+                self.tape.push(0);
+                self.origin = 0;
+            }
+            TapeMode::Circular(_) => self.tape.fill(0),
+        }
+        self.markers.clear();
+        self.loop_iterations.clear();
+        if self.profile.is_some() {
+            self.profile = Some(Profile::new(self.program.instructions.len()));
+        }
+    }
+
+    /// Sends the program's output to `output` rather than stdout, e.g. to capture it for comparison.
+    /// Comments are still printed to stdout.
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    /// Buffers the program's output until `policy` says to flush it. Any buffered output is always
+    /// flushed when [`Interpreter::run`] returns, even if it fails. As comments are printed
+    /// directly, they may appear before output that was written earlier.
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
+
+    pub fn set_print_level(&mut self, level: impl Into<CommentLevel>) {
+        self.printing_level = Some(level.into());
+    }
+
+    /// Should be called before [`Interpreter::run`], as switching to a circular tape truncates
+    /// (or extends) the tape to the given size.
+    pub fn set_tape_mode(&mut self, mode: TapeMode) {
+        if let TapeMode::Circular(size) = mode {
+            assert!(size > 0, "circular tape must have at least one cell");
+            self.tape.resize(size, 0);
+            self.tape_pointer %= size;
+        }
+        self.tape_mode = mode;
+    }
+
+    /// Makes a cell wrapping around (255 to 0 or 0 to 255) an error rather than silently wrapping.
+    pub fn set_overflow_check(&mut self, check: bool) {
+        self.overflow_check = check;
+    }
+
+    /// Makes growing the tape past `max_cells` cells an error, e.g. to stop a runaway `[>]` before
+    /// it runs out of memory. Unlimited by default, and a circular tape never grows.
+    pub fn set_max_cells(&mut self, max_cells: Option<usize>) {
+        self.max_cells = max_cells;
+    }
+
+    /// Prints a warning (and the tape) to stderr whenever a loop runs `threshold` iterations
+    /// without exiting, e.g. as a custom action left the pointer somewhere unexpected. Loops are
+    /// identified by the index of their `[`, which is in the stripped program if
+    /// [stripped](Interpreter::set_stripped).
+    pub fn set_loop_iteration_warn(&mut self, threshold: Option<u64>) {
+        self.loop_iteration_warn = threshold;
+    }
+
+    /// Calls `trace` after every instruction is executed (but not for comments or custom actions),
+    /// with the instruction, the tape pointer and the tape.
+    pub fn set_trace(&mut self, trace: impl FnMut(&Instruction, usize, Tape<'_>) + 'static) {
+        self.trace = Some(Box::new(trace));
+    }
+
+    /// Counts how many instructions are run in each comment section (from [`Item::comment`]), for
+    /// [`Interpreter::section_profile`]. This slows down running, and does nothing for a
+    /// [stripped](Interpreter::set_stripped) program, as that has no comments.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = enabled.then(|| Profile::new(self.program.instructions.len()));
+    }
+
+    /// The number of instructions run in each comment section so far, most first. Each instruction
+    /// only counts towards the innermost section it's in, and sections with the same text are
+    /// combined.
+    pub fn section_profile(&self) -> Vec<(String, u64)> {
+        let Some(profile) = &self.profile else {
+            return vec![];
+        };
+        let mut totals: Vec<(String, u64)> = vec![];
+        for (index, &count) in profile.counts.iter().enumerate().filter(|(_, &count)| count > 0) {
+            let InterpreterAction::Comment(text, _) = &self.program.instructions[index] else {
+                unreachable!("only comments start sections")
+            };
+            match totals.iter_mut().find(|(existing, _)| existing == text) {
+                Some((_, total)) => *total += count,
+                None => totals.push((text.clone(), count)),
+            }
+        }
+        totals.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        totals
+    }
+
+    /// Whether a failed assertion exits the process (the default), or is returned from
+    /// [`Interpreter::run`] so an embedding program can handle it.
+    pub fn set_assertions(&mut self, mode: AssertionMode) {
+        self.assertions = mode;
+    }
+
+    /// Runs a [stripped](Program::compile_stripped) copy of the program, skipping comments and
+    /// custom actions (and so also all markers and assertions) entirely.
+    pub fn set_stripped(&mut self, stripped: bool) {
+        self.stripped = stripped.then(|| self.program.compile_stripped());
     }
 
     pub fn run(&mut self) -> anyhow::Result<()> {
+        self.run_with(&mut None)
+    }
+
+    /// As [`Interpreter::run`], but also writes a snapshot of the tape (as [`Tape`]'s `Display`)
+    /// to `w` after every `every` instructions, and once more at the end, e.g. to step through
+    /// what a program does. Each snapshot is one line, starting with how many instructions have
+    /// been run.
+    pub fn run_recording(&mut self, every: usize, w: &mut impl Write) -> anyhow::Result<()> {
+        assert!(every > 0, "must record at least every instruction");
+        let mut recording = Some(Recording { every, executed: 0, w });
+        self.run_with(&mut recording)?;
+        let recording = recording.unwrap();
+        if !recording.executed.is_multiple_of(every as u64) {
+            writeln!(recording.w, "{:>10} {}", recording.executed, self.tape())?;
+        }
+        Ok(())
+    }
+
+    fn run_with(&mut self, recording: &mut Option<Recording<'_>>) -> anyhow::Result<()> {
+        let result = if let Some(stripped) = self.stripped.take() {
+            let result = self.run_stripped(&stripped, recording);
+            self.stripped = Some(stripped);
+            result
+        } else {
+            self.run_commented(recording)
+        };
+        let flushed = self.flush_output();
+        result?;
+        Ok(flushed?)
+    }
+
+    fn run_commented(&mut self, recording: &mut Option<Recording<'_>>) -> anyhow::Result<()> {
         loop {
             if self.instruction_pointer >= self.program.instructions.len() {
                 break;
@@ -47,48 +347,34 @@ impl Interpreter {
 
             let instruction = &self.program.instructions[self.instruction_pointer];
             match *instruction {
-                InterpreterAction::Instruction(Instruction::Left) => {
-                    self.tape_pointer = self.tape_pointer.checked_sub(1).unwrap();
-                }
-                InterpreterAction::Instruction(Instruction::Right) => {
-                    self.tape_pointer = self.tape_pointer.checked_add(1).unwrap();
-                    if self.tape_pointer >= self.tape.len() {
-                        self.tape.resize(self.tape_pointer + 1, 0);
+                InterpreterAction::Instruction(instruction) => {
+                    let jump = self.execute(instruction)?;
+                    if let Some(profile) = &mut self.profile {
+                        profile.record(instruction, jump);
                     }
-                }
-                InterpreterAction::Instruction(Instruction::Inc) => {
-                    self.tape[self.tape_pointer] = self.tape[self.tape_pointer].wrapping_add(1);
-                }
-                InterpreterAction::Instruction(Instruction::Dec) => {
-                    self.tape[self.tape_pointer] = self.tape[self.tape_pointer].wrapping_sub(1);
-                }
-                InterpreterAction::Instruction(Instruction::Input) => {
-                    let mut b = [0];
-                    if let Err(e) = self.input.read_exact(&mut b) {
-                        if e.kind() != std::io::ErrorKind::UnexpectedEof {
-                            return Err(e.into());
-                        }
+                    if self.loop_iteration_warn.is_some() {
+                        let start = if instruction == Instruction::End { self.program.pairs[self.instruction_pointer] } else { self.instruction_pointer };
+                        self.count_loop_iteration(instruction, jump, start);
                     }
-                    self.tape[self.tape_pointer] = b[0];
-                }
-                InterpreterAction::Instruction(Instruction::Output) => {
-                    let mut out = std::io::stdout();
-                    out.write_all(&[self.tape[self.tape_pointer]])?;
-                    out.flush()?;
-                }
-                InterpreterAction::Instruction(Instruction::Start) => {
-                    if self.tape[self.tape_pointer] == 0 {
-                        let matching = *self.program.pairs.get(&self.instruction_pointer).unwrap();
-                        self.instruction_pointer = matching;
+                    if let Some(trace) = &mut self.trace {
+                        let tape = Tape {
+                            at: self.tape_pointer,
+                            tape: &self.tape,
+                            origin: self.origin,
+                        };
+                        trace(&instruction, self.tape_pointer, tape);
                     }
-                }
-                InterpreterAction::Instruction(Instruction::End) => {
-                    if self.tape[self.tape_pointer] != 0 {
-                        let matching = *self.program.pairs.get(&self.instruction_pointer).unwrap();
-                        self.instruction_pointer = matching;
+                    if let Some(recording) = recording {
+                        recording.record(self.tape())?;
+                    }
+                    if jump {
+                        self.instruction_pointer = self.program.pairs[self.instruction_pointer];
                     }
                 }
                 InterpreterAction::Comment(ref text, level) => {
+                    if let Some(profile) = &mut self.profile {
+                        profile.sections.push(self.instruction_pointer);
+                    }
                     // if self.enable_printing {
                     if let Some(min_level) = self.printing_level {
                         if level >= min_level {
@@ -96,7 +382,11 @@ impl Interpreter {
                         }
                     }
                 }
-                InterpreterAction::EndComment => {}
+                InterpreterAction::EndComment => {
+                    if let Some(profile) = &mut self.profile {
+                        profile.sections.pop();
+                    }
+                }
                 InterpreterAction::Indent(_) => {}
                 InterpreterAction::Custom(ref custom) => {
                     // borrowck complains because `self.tape()` *could* borrow `self.markers` so we need
@@ -104,8 +394,19 @@ impl Interpreter {
                     let tape = Tape {
                         at: self.tape_pointer,
                         tape: &self.tape,
+                        origin: self.origin,
                     };
-                    custom.act(tape, self.tape_pointer, &mut self.markers)
+                    if let Err(e) = custom.act(tape, self.tape_pointer, &mut self.markers) {
+                        match self.assertions {
+                            AssertionMode::Fatal => {
+                                // Exiting skips `run`'s flush, and the output so far may help
+                                let _ = self.flush_output();
+                                println!("{e:#}");
+                                std::process::exit(1)
+                            }
+                            AssertionMode::Error => return Err(e),
+                        }
+                    }
                 }
             }
             self.instruction_pointer += 1;
@@ -114,23 +415,263 @@ impl Interpreter {
         Ok(())
     }
 
+    fn run_stripped(&mut self, stripped: &StrippedProgram, recording: &mut Option<Recording<'_>>) -> anyhow::Result<()> {
+        while let Some(&instruction) = stripped.instructions.get(self.instruction_pointer) {
+            let jump = self.execute(instruction)?;
+            if self.loop_iteration_warn.is_some() {
+                let start = if instruction == Instruction::End { stripped.pairs[self.instruction_pointer] } else { self.instruction_pointer };
+                self.count_loop_iteration(instruction, jump, start);
+            }
+            if let Some(trace) = &mut self.trace {
+                let tape = Tape {
+                    at: self.tape_pointer,
+                    tape: &self.tape,
+                    origin: self.origin,
+                };
+                trace(&instruction, self.tape_pointer, tape);
+            }
+            if let Some(recording) = recording {
+                recording.record(self.tape())?;
+            }
+            if jump {
+                self.instruction_pointer = stripped.pairs[self.instruction_pointer];
+            }
+            self.instruction_pointer += 1;
+        }
+
+        Ok(())
+    }
+
+    // Returns whether to jump to the matching bracket
+    fn execute(&mut self, instruction: Instruction) -> anyhow::Result<bool> {
+        match instruction {
+            Instruction::Left => match self.tape_mode {
+                TapeMode::Unbounded => self.tape_pointer = self.tape_pointer.checked_sub(1).unwrap(),
+                TapeMode::Bidirectional => {
+                    if self.tape_pointer == 0 {
+                        self.check_tape_growth()?;
+                        self.tape.insert(0, 0);
+                        self.origin += 1;
+                        for marker in self.markers.values_mut() {
+                            marker.at += 1;
+                        }
+                    } else {
+                        self.tape_pointer -= 1;
+                    }
+                }
+                TapeMode::Circular(size) => self.tape_pointer = self.tape_pointer.checked_sub(1).unwrap_or(size - 1),
+            },
+            Instruction::Right => match self.tape_mode {
+                TapeMode::Unbounded | TapeMode::Bidirectional => {
+                    if self.tape_pointer + 1 >= self.tape.len() {
+                        self.check_tape_growth()?;
+                    }
+                    self.tape_pointer = self.tape_pointer.checked_add(1).unwrap();
+                    if self.tape_pointer >= self.tape.len() {
+                        self.tape.resize(self.tape_pointer + 1, 0);
+                    }
+                }
+                TapeMode::Circular(size) => self.tape_pointer = (self.tape_pointer + 1) % size,
+            },
+            Instruction::Inc => {
+                let cell = &mut self.tape[self.tape_pointer];
+                if self.overflow_check && *cell == u8::MAX {
+                    bail!("cell {} overflowed at instruction {}", self.tape_pointer, self.instruction_pointer);
+                }
+                *cell = cell.wrapping_add(1);
+            }
+            Instruction::Dec => {
+                let cell = &mut self.tape[self.tape_pointer];
+                if self.overflow_check && *cell == 0 {
+                    bail!("cell {} underflowed at instruction {}", self.tape_pointer, self.instruction_pointer);
+                }
+                *cell = cell.wrapping_sub(1);
+            }
+            Instruction::Input => {
+                let mut b = [0];
+                if let Err(e) = self.input.read_exact(&mut b) {
+                    if e.kind() != std::io::ErrorKind::UnexpectedEof {
+                        return Err(e.into());
+                    }
+                    self.input_exhausted = true;
+                }
+                self.tape[self.tape_pointer] = b[0];
+            }
+            Instruction::Output => {
+                let byte = self.tape[self.tape_pointer];
+                match self.flush_policy {
+                    FlushPolicy::EachByte => {
+                        self.output.write_all(&[byte])?;
+                        self.output.flush()?;
+                    }
+                    FlushPolicy::OnNewline => {
+                        self.output_buffer.push(byte);
+                        if byte == b'\n' {
+                            self.flush_output()?;
+                        }
+                    }
+                    FlushPolicy::OnEnd => self.output_buffer.push(byte),
+                }
+            }
+            Instruction::Start => return Ok(self.tape[self.tape_pointer] == 0),
+            Instruction::End => return Ok(self.tape[self.tape_pointer] != 0),
+        }
+        Ok(false)
+    }
+
+    // `start` is the index of the loop's `[`
+    fn count_loop_iteration(&mut self, instruction: Instruction, jump: bool, start: usize) {
+        let Some(threshold) = self.loop_iteration_warn else {
+            return;
+        };
+        match instruction {
+            Instruction::Start if !jump => {
+                self.loop_iterations.insert(start, 0);
+            }
+            Instruction::End if jump => {
+                let iterations = self.loop_iterations.entry(start).or_insert(0);
+                *iterations += 1;
+                if *iterations == threshold {
+                    eprintln!("loop at instruction {start} has run {threshold} iterations without exiting\n{}", self.tape());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_tape_growth(&self) -> anyhow::Result<()> {
+        match self.max_cells {
+            Some(max_cells) if self.tape.len() >= max_cells => {
+                bail!("tape exceeded {max_cells} cells at instruction {}", self.instruction_pointer)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn flush_output(&mut self) -> std::io::Result<()> {
+        self.output.write_all(&self.output_buffer)?;
+        self.output_buffer.clear();
+        self.output.flush()
+    }
+
+    /// Places (or moves) the marker `name` on `cell`, as [`Item::add_marker`] would from inside
+    /// the program, e.g. so assertions in a section being tested have something to check against.
+    /// [`Interpreter::reset`] clears all markers, so this should be called after it.
+    #[track_caller]
+    pub fn set_marker(&mut self, name: impl Into<String>, cell: usize) {
+        let marker = Marker {
+            at: cell,
+            created: Location::caller(),
+        };
+        self.markers.insert(name.into(), marker);
+    }
+
+    /// The markers placed so far (and not yet removed), by name.
+    pub fn markers(&self) -> &HashMap<String, Marker> {
+        &self.markers
+    }
+
+    /// Whether an `,` has tried to read past the end of the input, which reads a zero just as a
+    /// real zero byte does. Only cleared by [`Interpreter::reset`].
+    pub fn input_exhausted(&self) -> bool {
+        self.input_exhausted
+    }
+
     pub fn tape(&self) -> Tape<'_> {
         Tape {
             at: self.tape_pointer,
             tape: &self.tape,
+            origin: self.origin,
         }
     }
+
+    /// Writes every cell as space-separated decimals on one line, with the cell under the pointer
+    /// prefixed by `*`, e.g. `0 *15 3`. Unlike [`Tape`]'s `Display`, this is meant to be parsed or
+    /// diffed, so the format won't change.
+    pub fn dump_tape(&self, w: &mut impl Write) -> std::io::Result<()> {
+        for (i, c) in self.tape.iter().enumerate() {
+            let sep = if i == 0 { "" } else { " " };
+            let marker = if i == self.tape_pointer { "*" } else { "" };
+            write!(w, "{sep}{marker}{c}")?;
+        }
+        writeln!(w)
+    }
 }
 
 pub struct Tape<'a> {
     at: usize,
     tape: &'a [u8],
+    origin: usize,
+}
+
+impl<'a> Tape<'a> {
+    /// The `len` cells starting at `start`, counted from the starting cell as [`Tape::position`]
+    /// is, panicking if that goes past the cells the program has reached so far.
+    pub fn window(&self, start: isize, len: usize) -> &'a [u8] {
+        let start = self.origin.checked_add_signed(start).expect("window starts left of the tape");
+        &self.tape[start..start + len]
+    }
+
+    /// Where the pointer is in the underlying cells, counting from the leftmost cell. This moves
+    /// whenever a [`TapeMode::Bidirectional`] tape grows leftwards, so see [`Tape::position`] for
+    /// where the pointer is in the program's terms.
+    pub fn pointer(&self) -> usize {
+        self.at
+    }
+
+    /// Where the pointer is relative to the starting cell, which is only ever negative for
+    /// [`TapeMode::Bidirectional`].
+    pub fn position(&self) -> isize {
+        self.at as isize - self.origin as isize
+    }
+
+    /// Where the starting cell is, which is only ever nonzero for [`TapeMode::Bidirectional`].
+    ///
+    /// [`Tape::pointer`] and indexing count from the leftmost cell, not from this.
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    /// Renders the tape one region per line, as [`Display`] does for the whole tape, where each
+    /// region starts at one of the `labels`' cells and extends until the next one (or the end of
+    /// the tape).
+    pub fn render_regions(&self, labels: &[(usize, &str)]) -> String {
+        use std::fmt::Write as _;
+
+        let mut labels = labels.to_vec();
+        labels.sort_by_key(|&(start, _)| start);
+        if labels.first().is_none_or(|&(start, _)| start > 0) {
+            labels.insert(0, (0, ""));
+        }
+        let width = labels.iter().map(|(_, label)| label.len()).max().unwrap_or(0);
+
+        let mut s = String::new();
+        for (i, &(start, label)) in labels.iter().enumerate() {
+            let end = labels.get(i + 1).map_or(self.tape.len(), |&(next, _)| next).min(self.tape.len());
+            if start >= end {
+                continue;
+            }
+            let _ = write!(s, "{label:>width$} {start:5}:");
+            for (j, c) in self.tape[start..end].iter().enumerate() {
+                if start + j == self.at {
+                    let _ = write!(s, " [{c:3}]");
+                } else {
+                    let _ = write!(s, " {c:3}");
+                }
+            }
+            s.push('\n');
+        }
+        s
+    }
 }
 
 impl Display for Tape<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "[")?;
         for (i, c) in self.tape.iter().enumerate() {
+            if i == self.origin && self.origin != 0 {
+                write!(f, " |")?;
+            }
             if i == self.at {
                 write!(f, " [{c:3}]")?;
             } else {
@@ -153,35 +694,74 @@ impl Deref for Tape<'_> {
 #[derive(Debug, Clone)]
 pub struct Program {
     instructions: Vec<InterpreterAction>,
-    pairs: HashMap<usize, usize>,
+    // Indexed by instruction position, `NO_PAIR` for anything other than a bracket
+    pairs: Vec<usize>,
 }
 
 impl Program {
-    pub fn build(instructions: Vec<InterpreterAction>) -> anyhow::Result<Self> {
-        let mut pairs = HashMap::new();
-
-        let mut stack = vec![];
-        for (i, ins) in instructions.iter().enumerate() {
-            match ins {
-                InterpreterAction::Instruction(Instruction::Start) => {
-                    stack.push(i);
-                }
-                InterpreterAction::Instruction(Instruction::End) => {
-                    let matching = stack.pop().ok_or_else(|| anyhow!("unopened close"))?;
-                    pairs.insert(i, matching);
-                    pairs.insert(matching, i);
-                }
-                _ => {}
-            }
-        }
-        if !stack.is_empty() {
-            bail!("unclosed open[s]")
-        }
+    /// Builds `item` into a program, first running the static [balance check](Item::check_balance)
+    /// over it, so that structural mistakes are caught before the program is ever run. The item
+    /// is [flattened](Item::flatten) before being built.
+    pub fn build(item: Item) -> anyhow::Result<Self> {
+        // Checked before flattening, as the check uses the nesting for context in its errors
+        item.check_balance().context("program failed the pointer balance check")?;
+        Self::from_actions(item.flatten().build())
+    }
 
+    fn from_actions(instructions: Vec<InterpreterAction>) -> anyhow::Result<Self> {
+        let pairs = match_brackets(instructions.iter().map(InterpreterAction::as_instruction))?;
         Ok(Self { instructions, pairs })
     }
 
+    /// Builds each of `parts` one after another as a single program, e.g. to run just one section
+    /// of a larger program between a custom setup and some checks. Brackets are matched over the
+    /// whole program, so a loop may start in one part and end in another.
+    pub fn concat(parts: Vec<Item>) -> anyhow::Result<Self> {
+        Self::build(Item::Sequence(parts))
+    }
+
+    /// Parses brainfuck source, which must only contain the 8 instructions, whitespace and `//`
+    /// line comments, as [`Item::parse`] does.
+    ///
+    /// This is the inverse of [`Program::as_text`] (or [`Program::as_text_uncommented`] or
+    /// [`Program::as_text_clean`]).
+    pub fn from_text(s: &str) -> anyhow::Result<Self> {
+        Self::build(Item::parse(s)?)
+    }
+
+    /// Iterates over the program's actions, along with the index of the matching bracket for
+    /// each `[` and `]`.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &InterpreterAction, Option<usize>)> + '_ {
+        self.instructions
+            .iter()
+            .enumerate()
+            .map(|(i, action)| (i, action, Some(self.pairs[i]).filter(|&it| it != NO_PAIR)))
+    }
+
+    /// Drops everything but the instructions themselves, for faster interpretation.
+    pub fn compile_stripped(&self) -> StrippedProgram {
+        let instructions = self
+            .instructions
+            .iter()
+            .filter_map(InterpreterAction::as_instruction)
+            .collect::<Vec<_>>();
+        let pairs = match_brackets(instructions.iter().copied().map(Some)).expect("brackets were already matched");
+        StrippedProgram { instructions, pairs }
+    }
+
+    /// The program laid out with indentation for loops, and each comment on its own `// ` line,
+    /// which [`Program::from_text`] parses back into the same program.
     pub fn as_text(&self) -> String {
+        self.layout(true)
+    }
+
+    /// As [`Program::as_text`], but with the comments left out (though still starting new lines),
+    /// e.g. for brainfuck implementations that don't understand `//` comments.
+    pub fn as_text_uncommented(&self) -> String {
+        self.layout(false)
+    }
+
+    fn layout(&self, comments: bool) -> String {
         let mut s = String::new();
         let mut indent = 0_usize;
         let mut indent_str = String::new();
@@ -193,10 +773,15 @@ impl Program {
                 }
                 InterpreterAction::Comment(comment, _) => {
                     s.push('\n');
-                    s.push_str(&indent_str);
-                    s.push_str("// ");
-                    s.push_str(comment);
-                    s.push('\n');
+                    if comments {
+                        // Each line separately, so none of it is read back as instructions
+                        for line in comment.lines() {
+                            s.push_str(&indent_str);
+                            s.push_str("// ");
+                            s.push_str(line);
+                            s.push('\n');
+                        }
+                    }
                     s.push_str(&indent_str);
                 }
                 InterpreterAction::EndComment => {
@@ -228,6 +813,39 @@ impl Program {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct StrippedProgram {
+    instructions: Vec<Instruction>,
+    pairs: Vec<usize>,
+}
+
+const NO_PAIR: usize = usize::MAX;
+
+fn match_brackets(instructions: impl Iterator<Item = Option<Instruction>>) -> anyhow::Result<Vec<usize>> {
+    let mut pairs = vec![];
+
+    let mut stack = vec![];
+    for (i, ins) in instructions.enumerate() {
+        pairs.push(NO_PAIR);
+        match ins {
+            Some(Instruction::Start) => {
+                stack.push(i);
+            }
+            Some(Instruction::End) => {
+                let matching = stack.pop().ok_or_else(|| anyhow!("unopened close at instruction {i}"))?;
+                pairs[i] = matching;
+                pairs[matching] = i;
+            }
+            _ => {}
+        }
+    }
+    if let Some(first) = stack.first() {
+        bail!("{} unclosed open[s], the first at instruction {first}", stack.len())
+    }
+
+    Ok(pairs)
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Instruction {
     Left,
@@ -280,7 +898,356 @@ impl Marker {
         self.at
     }
 
-    pub fn creation_location(&self) -> &'static Location {
+    pub fn creation_location(&self) -> &'static Location<'static> {
         self.created
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{cell::RefCell, io::Cursor, rc::Rc};
+
+    use super::*;
+
+    /// Runs `item` with the tape starting out as `cells` and the pointer on the first of them,
+    /// returning the tape and the output afterwards.
+    pub(crate) fn run_with_output(cells: &[u8], item: Item) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        // Only as far as the last of `cells`, so the tape isn't any longer than it needs to be
+        let setup = cells
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &cell)| [Item::from(Instruction::Right).repeat(usize::from(i > 0)), Item::from(Instruction::Inc).repeat(cell.into())])
+            .chain([Item::from(Instruction::Left).repeat(cells.len().saturating_sub(1))])
+            .collect::<Vec<_>>();
+        let program = Program::build(Item::Sequence(vec![Item::Sequence(setup), item]))?;
+
+        let output = SharedOutput::default();
+        let mut interpreter = Interpreter::new(program, Cursor::new(vec![]));
+        interpreter.set_output(output.clone());
+        interpreter.set_assertions(AssertionMode::Error);
+        interpreter.run()?;
+        let tape = interpreter.tape().to_vec();
+        Ok((tape, output.0.take()))
+    }
+
+    /// As [`run_with_output`], for just the tape.
+    pub(crate) fn run_on(cells: &[u8], item: Item) -> anyhow::Result<Vec<u8>> {
+        run_with_output(cells, item).map(|(tape, _)| tape)
+    }
+
+    // Adds the first two cells into the third and prints it, with comments and indentation
+    fn sample_program() -> Program {
+        use crate::build::Loop;
+        use Instruction::*;
+
+        Program::build(Item::Sequence(vec![
+            Item::Comment("set up 2 + 3, then add them.".to_owned(), CommentLevel::DEBUG),
+            Item::from(Inc).repeat(2),
+            Right.into(),
+            Item::from(Inc).repeat(3),
+            Item::EndComment,
+            Loop::new(vec![Dec.into(), Right.into(), Inc.into(), Left.into()]).indent().into(),
+            Left.into(),
+            Loop::new(vec![Dec.into(), Right.into(), Right.into(), Inc.into(), Left.into(), Left.into()]).indent().into(),
+            Right.into(),
+            Right.into(),
+            Item::Comment("[print the total]".to_owned(), CommentLevel::INFO),
+            Output.into(),
+            Item::EndComment,
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn as_text_round_trip() {
+        // The comments include instruction characters, which mustn't be read as instructions
+        let program = sample_program();
+        let text = program.as_text();
+        assert!(text.contains("// set up 2 + 3, then add them."), "{text}");
+        let parsed = Program::from_text(&text).unwrap();
+        assert_eq!(parsed.as_text_clean(), program.as_text_clean());
+        assert_eq!(Program::from_text(&parsed.as_text()).unwrap().as_text_clean(), program.as_text_clean());
+
+        let program = Program::from_actions(vec![
+            InterpreterAction::Comment("two\nlines +".to_owned(), CommentLevel::INFO),
+            InterpreterAction::Instruction(Instruction::Inc),
+            InterpreterAction::EndComment,
+        ])
+        .unwrap();
+        assert_eq!(Program::from_text(&program.as_text()).unwrap().as_text_clean(), "+");
+    }
+
+    #[test]
+    fn from_text_round_trip() {
+        let program = sample_program();
+        let text = program.as_text_clean();
+        assert_eq!(text, "++>+++[->+<]<[->>+<<]>>.");
+        assert_eq!(Program::from_text(&text).unwrap().as_text_clean(), text);
+        assert_eq!(Program::from_text(&program.as_text_uncommented()).unwrap().as_text_clean(), text);
+
+        let err = Program::from_text("+\n+x").unwrap_err();
+        assert_eq!(err.to_string(), "unknown byte 0x78 at offset 3 (line 2, column 2)");
+        assert!(Program::from_text("[[]").is_err());
+    }
+
+    #[test]
+    fn build_checks_balance() {
+        let err = Program::build(Item::from(build::Loop::new(vec![bf!(">-")]))).unwrap_err();
+        assert_eq!(err.to_string(), "program failed the pointer balance check");
+        // Parsed loops aren't checked, so any program text still builds
+        assert!(Program::from_text("+[>-]").is_ok());
+        assert!(Program::concat(vec![bf!("+["), bf!(">-]")]).is_ok());
+    }
+
+    #[test]
+    fn max_cells_stops_runaway() {
+        let program = Program::from_text("+[>+]").unwrap();
+        let mut interpreter = Interpreter::new(program.clone(), Cursor::new(vec![]));
+        interpreter.set_max_cells(Some(100));
+        let err = interpreter.run().unwrap_err();
+        assert!(err.to_string().starts_with("tape exceeded 100 cells"), "{err}");
+        assert_eq!(interpreter.tape().len(), 100);
+
+        // The stripped program checks as well
+        let mut interpreter = Interpreter::new(program, Cursor::new(vec![]));
+        interpreter.set_max_cells(Some(100));
+        interpreter.set_stripped(true);
+        assert!(interpreter.run().is_err());
+
+        // Leftwards on a bidirectional tape too
+        let mut interpreter = Interpreter::new(Program::from_text("+[<+]").unwrap(), Cursor::new(vec![]));
+        interpreter.set_tape_mode(TapeMode::Bidirectional);
+        interpreter.set_max_cells(Some(100));
+        assert!(interpreter.run().is_err());
+        assert_eq!(interpreter.tape().len(), 100);
+
+        // A program that stays within the limit is unaffected
+        let mut interpreter = Interpreter::new(Program::from_text("+[>+>+<<-]").unwrap(), Cursor::new(vec![]));
+        interpreter.set_max_cells(Some(3));
+        interpreter.run().unwrap();
+    }
+
+    #[test]
+    fn circular_wraps() {
+        // `<` from the first cell wraps to the last, and `>` from the last back to the first
+        let mut interpreter = Interpreter::new(Program::from_text("<+++<++>>+").unwrap(), Cursor::new(vec![]));
+        interpreter.set_tape_mode(TapeMode::Circular(3));
+        interpreter.run().unwrap();
+        assert_eq!(*interpreter.tape(), [1, 2, 3]);
+        assert_eq!(interpreter.tape().pointer(), 0);
+
+        // The stripped program wraps as well, and the tape never grows
+        let mut interpreter = Interpreter::new(Program::from_text(">>>>>+<<+").unwrap(), Cursor::new(vec![]));
+        interpreter.set_tape_mode(TapeMode::Circular(4));
+        interpreter.set_stripped(true);
+        interpreter.run().unwrap();
+        assert_eq!(*interpreter.tape(), [0, 1, 0, 1]);
+        assert_eq!(interpreter.tape().pointer(), 3);
+    }
+
+    #[test]
+    fn reset_reruns() {
+        // Reads into the first cell and the last, wrapping around
+        let mut interpreter = Interpreter::new(Program::from_text(",<,").unwrap(), Cursor::new(vec![1, 2]));
+        interpreter.set_tape_mode(TapeMode::Circular(4));
+        interpreter.set_print_level(CommentLevel::SECTION);
+        interpreter.set_marker("x", 1);
+        interpreter.run().unwrap();
+        assert_eq!(*interpreter.tape(), [1, 0, 0, 2]);
+        assert!(!interpreter.input_exhausted());
+
+        interpreter.reset(Cursor::new(vec![5]));
+        assert_eq!(*interpreter.tape(), [0; 4]);
+        assert_eq!(interpreter.tape().pointer(), 0);
+        assert!(interpreter.markers().is_empty());
+        assert_eq!(interpreter.printing_level, Some(CommentLevel::SECTION));
+        // Still circular, and reading from the new input from its start
+        interpreter.run().unwrap();
+        assert_eq!(*interpreter.tape(), [5, 0, 0, 0]);
+        assert!(interpreter.input_exhausted());
+
+        // An unbounded tape shrinks back to a single cell
+        let mut interpreter = Interpreter::new(Program::from_text(">>,").unwrap(), Cursor::new(vec![7]));
+        interpreter.run().unwrap();
+        assert_eq!(*interpreter.tape(), [0, 0, 7]);
+        interpreter.reset(Cursor::new(vec![]));
+        assert_eq!(*interpreter.tape(), [0]);
+    }
+
+    #[test]
+    fn section_profile_counts() {
+        use crate::build::Loop;
+        use Instruction::*;
+
+        let program = Program::build(Item::Sequence(vec![
+            Item::from(Inc).repeat(3).comment("setup", CommentLevel::INFO),
+            // `[` once, then `-` and `]` for each of the 3 iterations
+            Item::from(Loop::new(vec![Dec.into()])).comment("count down", CommentLevel::INFO),
+            // Only the innermost section counts an instruction
+            Item::Sequence(vec![Right.into(), Item::from(Inc).comment("inner", CommentLevel::INFO)]).comment("outer", CommentLevel::INFO),
+            // Outside any section
+            Left.into(),
+            // Combined with the first "setup"
+            Item::from(Inc).comment("setup", CommentLevel::INFO),
+        ]))
+        .unwrap();
+        let mut interpreter = Interpreter::new(program, Cursor::new(vec![]));
+        assert_eq!(interpreter.section_profile(), []);
+        interpreter.set_profiling(true);
+        interpreter.run().unwrap();
+        let expected = [("count down", 7), ("setup", 4), ("outer", 1), ("inner", 1)].map(|(text, count)| (text.to_owned(), count));
+        assert_eq!(interpreter.section_profile(), expected);
+
+        // A rerun starts counting again
+        interpreter.reset(Cursor::new(vec![]));
+        assert_eq!(interpreter.section_profile(), []);
+    }
+
+    #[test]
+    fn flush_policy() {
+        // What had been written each time the output was flushed
+        let flushes = |policy, program: Item| {
+            let output = FlushLog::default();
+            let mut interpreter = Interpreter::new(Program::build(program).unwrap(), Cursor::new(vec![]));
+            interpreter.set_output(output.clone());
+            interpreter.set_flush_policy(policy);
+            interpreter.set_assertions(AssertionMode::Error);
+            let result = interpreter.run();
+            (result, output.flushed.take())
+        };
+
+        let (result, flushed) = flushes(FlushPolicy::EachByte, Item::emit_str("a\nb"));
+        result.unwrap();
+        assert_eq!(flushed, ["a", "\n", "b"]);
+        let (result, flushed) = flushes(FlushPolicy::OnNewline, Item::emit_str("a\nb"));
+        result.unwrap();
+        assert_eq!(flushed, ["a\n", "b"]);
+        let (result, flushed) = flushes(FlushPolicy::OnEnd, Item::emit_str("a\nb"));
+        result.unwrap();
+        assert_eq!(flushed, ["a\nb"]);
+
+        // Still flushed when the program fails
+        let failing = Item::Sequence(vec![Item::emit_str("ab"), Item::assert_position(1, "fail")]);
+        let (result, flushed) = flushes(FlushPolicy::OnEnd, failing);
+        assert!(result.is_err());
+        assert_eq!(flushed, ["ab"]);
+    }
+
+    #[test]
+    fn loop_iteration_counts() {
+        // The outer loop at 2 runs twice, and the inner loop at 7 three times each time it's entered,
+        // which is one and two jumps back
+        let program = Program::from_text("++[>+++[-]<-]").unwrap();
+        let iterations = |threshold, stripped| {
+            let mut interpreter = Interpreter::new(program.clone(), Cursor::new(vec![]));
+            interpreter.set_loop_iteration_warn(threshold);
+            interpreter.set_stripped(stripped);
+            interpreter.run().unwrap();
+            let mut iterations = interpreter.loop_iterations.into_iter().collect::<Vec<_>>();
+            iterations.sort();
+            iterations
+        };
+        // Counted per entry, so the inner loop's count starts again rather than reaching 4
+        assert_eq!(iterations(Some(100), false), [(2, 1), (7, 2)]);
+        assert_eq!(iterations(Some(100), true), [(2, 1), (7, 2)]);
+        // Past the threshold, which only warns
+        assert_eq!(iterations(Some(1), false), [(2, 1), (7, 2)]);
+        assert_eq!(iterations(None, false), []);
+    }
+
+    #[test]
+    fn set_marker_before_run() {
+        use Instruction::Right;
+
+        let program = Program::build(Item::Sequence(vec![
+            Item::from(Right).repeat(2),
+            Item::assert_marker_offset("start", 2, "two along"),
+            Item::add_marker("end"),
+        ]))
+        .unwrap();
+        let mut interpreter = Interpreter::new(program, Cursor::new(vec![]));
+        interpreter.set_assertions(AssertionMode::Error);
+        interpreter.set_marker("start", 1);
+        // Moved rather than duplicated
+        interpreter.set_marker("start", 0);
+        interpreter.run().unwrap();
+        let mut markers = interpreter.markers().iter().map(|(name, marker)| (name.as_str(), marker.at())).collect::<Vec<_>>();
+        markers.sort();
+        assert_eq!(markers, [("end", 2), ("start", 0)]);
+
+        // Without it, the assertion has nothing to check against
+        interpreter.reset(Cursor::new(vec![]));
+        let err = interpreter.run().unwrap_err();
+        assert!(err.to_string().contains("marker \"start\" does not exist"), "{err}");
+    }
+
+    fn run_bidirectional(item: Item) -> anyhow::Result<Vec<u8>> {
+        let mut interpreter = Interpreter::new(Program::build(item)?, Cursor::new(vec![]));
+        interpreter.set_tape_mode(TapeMode::Bidirectional);
+        interpreter.set_assertions(AssertionMode::Error);
+        interpreter.run()?;
+        Ok(interpreter.tape().to_vec())
+    }
+
+    #[test]
+    fn bidirectional_position() {
+        let left = |n| Item::from(Instruction::Left).repeat(n);
+        let right = |n| Item::from(Instruction::Right).repeat(n);
+        let inc = |n| Item::from(Instruction::Inc).repeat(n);
+        let tape = run_bidirectional(Item::Sequence(vec![
+            inc(1),
+            left(2),
+            inc(2),
+            right(2),
+            // The tape has grown two cells leftwards, but the pointer is back on the starting cell
+            Item::assert_position(0, "back on the start"),
+            Item::named_custom("check window", |tape, _, _| {
+                assert_eq!(tape.position(), 0);
+                assert_eq!(tape.pointer(), 2);
+                assert_eq!(tape.window(-2, 3), [2, 0, 1]);
+                Ok(())
+            }),
+        ]))
+        .unwrap();
+        assert_eq!(tape, [2, 0, 1]);
+
+        let err = run_bidirectional(Item::Sequence(vec![left(1), Item::assert_position(0, "left of the start")])).unwrap_err();
+        assert!(err.to_string().contains("actual  : -1"), "{err}");
+    }
+
+    // Records what's been written between each flush, ignoring flushes with nothing new
+    #[derive(Clone, Default)]
+    struct FlushLog {
+        pending: Rc<RefCell<Vec<u8>>>,
+        flushed: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Write for FlushLog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.pending.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            let pending = self.pending.take();
+            if !pending.is_empty() {
+                self.flushed.borrow_mut().push(String::from_utf8(pending).unwrap());
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -1,17 +1,21 @@
 use std::{
+    ffi::OsString,
     io::Cursor,
+    path::Path,
     rc::Rc,
     sync::atomic::{AtomicBool, Ordering},
 };
 
 use bf_runner::{
+    bf,
     build::{
-        drain,
-        num::{operate, ByteSub, DecimalAdd, DecimalSub},
-        offset_from, offset_to_insns, zero_cell, zero_cell_up, Buildable, Item, Loop,
+        drain, drain_scaled, move_cell,
+        num::{operate, write_number, ByteSub, DecimalAdd, DecimalCompare, DecimalDivide, DecimalSub},
+        offset_from, offset_to_insns, zero_cell, zero_cell_up, Item, Loop,
     },
-    Instruction, Interpreter, Program,
+    CommentLevel, Instruction, Interpreter, Program,
 };
+use anyhow::{anyhow, bail, Context};
 use tap::Conv;
 
 /*
@@ -20,7 +24,6 @@ Assumptions (non-exclusive):
     Valid header
         Little endian
         Version (2, 4)
-        snap_len is u16::MAX
         link_type is 1 (Ethernet)
         Correct size
     Ethernet frames
@@ -36,6 +39,7 @@ Assumptions (non-exclusive):
         Upon EOF, Input instructions set cell to 0
         At least one packet
         At least one each of UDP and TCP
+        At most `Positions::LIST_CAPACITY` distinct destinations
 
  */
 
@@ -64,47 +68,25 @@ fn discard_header() -> Item {
         Instruction::Left.into(),
         Item::assert_position(0, "discard header does not move head"),
     ])
-    .comment("discard header", 200)
+    .comment("discard header", CommentLevel::SECTION)
 }
 
 fn read_u16() -> Item {
-    Item::Sequence(vec![
-        Instruction::Input.into(),
-        Instruction::Right.into(),
-        Instruction::Input.into(),
-    ])
+    bf!(",>,")
 }
 
 fn read_u32() -> Item {
-    Item::Sequence(vec![
-        Instruction::Input.into(),
-        Instruction::Right.into(),
-        Instruction::Input.into(),
-        Instruction::Right.into(),
-        Instruction::Input.into(),
-        Instruction::Right.into(),
-        Instruction::Input.into(),
-    ])
+    bf!(",>,>,>,")
 }
 
 fn read_u32_le() -> Item {
+    use Instruction::{Input, Left, Right};
     Item::Sequence(vec![
-        Item::repeat(Instruction::Right.into(), 3),
-        Instruction::Input.into(),
-        Instruction::Left.into(),
-        Instruction::Input.into(),
-        Instruction::Left.into(),
-        Instruction::Input.into(),
-        Instruction::Left.into(),
-        Instruction::Input.into(),
+        Item::repeat(Right.into(), 3),
+        vec![Input, Left, Input, Left, Input, Left, Input].into(),
     ])
 }
 
-fn find_non_zero_cell_right() -> Item {
-    // "Find a non-zeroed cell" from https://esolangs.org/wiki/Brainfuck_algorithms
-    Item::parse("+[>[<-]<[->+<]>]>").expect("should be valid")
-}
-
 fn zero_check(offset: isize) -> Item {
     Item::Sequence(vec![
         Loop::new(vec![
@@ -152,24 +134,29 @@ fn packet_loop_after_check() -> Item {
             Instruction::Inc.into(),
             Instruction::Left.into(),
             Loop::new(vec![
-                Item::Comment("if TCP".to_owned(), 160),
+                Item::Comment("if TCP".to_owned(), CommentLevel::INFO),
                 // If !0 <-> protocol=0x06 <-> TCP
                 Instruction::Right.into(),
                 Instruction::Dec.into(),
                 Instruction::Left.into(),
+                offset_to_insns(offset_from(Positions::PACKET_IP_PROTOCOL, Positions::PACKET_IS_TCP)),
+                Instruction::Inc.into(),
+                offset_to_insns(offset_from(Positions::PACKET_IS_TCP, Positions::PACKET_IP_PROTOCOL)),
                 zero_cell_up(),
             ])
             .indent()
             .conv::<Item>(),
             Instruction::Right.into(),
             Loop::new(vec![
-                Item::Comment("else (if UDP)".to_owned(), 160),
+                Item::Comment("else (if UDP)".to_owned(), CommentLevel::INFO),
                 // If 0 <-> protocol=0x11 <-> UDP
                 Instruction::Dec.into(),
                 Item::add_marker("else start"),
                 offset_to_insns(offset_from(Positions::PACKET_IP_PROTOCOL + 1, Positions::NO_UDP)),
                 operate::<DecimalAdd<{ Positions::NO_UDP_WIDTH }>>(offset_from(Positions::NO_UDP, Positions::SCRATCH_SPACE_START)),
-                offset_to_insns(offset_from(Positions::NO_UDP, Positions::PACKET_IP_PROTOCOL + 1)),
+                offset_to_insns(offset_from(Positions::NO_UDP, Positions::PACKET_IS_UDP)),
+                Instruction::Inc.into(),
+                offset_to_insns(offset_from(Positions::PACKET_IS_UDP, Positions::PACKET_IP_PROTOCOL + 1)),
                 Item::assert_marker_offset("else start", 0, "branch end"),
                 Item::remove_marker("else start"),
             ])
@@ -177,10 +164,29 @@ fn packet_loop_after_check() -> Item {
             .conv::<Item>(),
             Instruction::Left.into(),
         ])
-        .comment("handle protocol", 100)
+        .comment("handle protocol", CommentLevel::DEBUG)
     }
 
     fn handle_total_length() -> Item {
+        // Adds a byte to `counter` if `flag` is set, leaving `flag` as it was
+        fn add_byte_if(flag: usize, counter: usize) -> Item {
+            Item::Sequence(vec![
+                offset_to_insns(offset_from(Positions::PACKET_IP_TOTAL_LENGTH_START, flag)),
+                Loop::new(vec![
+                    Instruction::Dec.into(),
+                    offset_to_insns(offset_from(flag, Positions::PACKET_PROTOCOL_SCRATCH)),
+                    Instruction::Inc.into(),
+                    offset_to_insns(offset_from(Positions::PACKET_PROTOCOL_SCRATCH, counter)),
+                    operate::<DecimalAdd<{ Positions::PROTOCOL_BYTES_WIDTH }>>(offset_from(counter, Positions::SCRATCH_SPACE_START)),
+                    offset_to_insns(offset_from(counter, flag)),
+                ])
+                .into(),
+                offset_to_insns(offset_from(flag, Positions::PACKET_PROTOCOL_SCRATCH)),
+                move_cell(offset_from(Positions::PACKET_PROTOCOL_SCRATCH, flag)),
+                offset_to_insns(offset_from(Positions::PACKET_PROTOCOL_SCRATCH, Positions::PACKET_IP_TOTAL_LENGTH_START)),
+            ])
+        }
+
         fn collapse_condition() -> Item {
             Item::Sequence(vec![
                 Item::assert_position(Positions::PACKET_IP_TOTAL_LENGTH_START, "collapse total length call"),
@@ -216,14 +222,14 @@ fn packet_loop_after_check() -> Item {
         Item::Sequence(vec![
             Item::assert_position(Positions::PACKET_IP_TOTAL_LENGTH_START, "total length call"),
             Instruction::Right.conv::<Item>().repeat(4),
-            Instruction::Inc.conv::<Item>().repeat(20),
-            Loop::new(vec![
-                Instruction::Left.conv::<Item>().repeat(3),
-                operate::<ByteSub<2>>(1),
-                Instruction::Right.conv::<Item>().repeat(3),
-                Instruction::Dec.into(),
-            ])
-            .into(),
+            Item::counted_loop(
+                20,
+                vec![
+                    Instruction::Left.conv::<Item>().repeat(3),
+                    operate::<ByteSub<2>>(1),
+                    Instruction::Right.conv::<Item>().repeat(3),
+                ],
+            ),
             Instruction::Left.conv::<Item>().repeat(4),
             collapse_condition(),
             Loop::new(vec![
@@ -237,6 +243,8 @@ fn packet_loop_after_check() -> Item {
                 operate::<DecimalAdd<{ Positions::TRANSPORT_BYTES_WIDTH }>>(offset_from(Positions::TRANSPORT_BYTES, Positions::SCRATCH_SPACE_START)),
                 offset_to_insns(offset_from(Positions::TRANSPORT_BYTES, Positions::PACKET_IP_TOTAL_LENGTH_START)),
                 Item::assert_position(Positions::PACKET_IP_TOTAL_LENGTH_START, "after transport bytes inc"),
+                add_byte_if(Positions::PACKET_IS_TCP, Positions::TCP_BYTES),
+                add_byte_if(Positions::PACKET_IS_UDP, Positions::UDP_BYTES),
                 collapse_condition(),
             ])
             .indent()
@@ -259,6 +267,8 @@ fn packet_loop_after_check() -> Item {
         handle_protocol(),
         Item::repeat(Instruction::Input.into(), 2),
         Item::repeat(Instruction::Input.into(), 4), // Discard source addr
+        // The last byte read is left behind, and this cell is scratch for printing the total later
+        zero_cell(),
         // Read 2*4 - dest addr
         Item::assert_position(Positions::PACKET_IP_DEST_START - 10, "before IP"),
         offset_to_insns(10),
@@ -273,20 +283,21 @@ fn packet_loop_after_check() -> Item {
         )),
         handle_total_length(),
         Item::assert_position(Positions::PACKET_IP_TOTAL_LENGTH_SCRATCH + 1, "total length done"),
-        offset_to_insns(offset_from(
-            Positions::PACKET_IP_TOTAL_LENGTH_SCRATCH + 1,
-            Positions::PACKET_LOOP_START,
-        )),
-        Item::Comment("end of packet".to_owned(), 190),
+        offset_to_insns(offset_from(Positions::PACKET_IP_TOTAL_LENGTH_SCRATCH + 1, Positions::PACKET_IS_TCP)),
+        zero_cell(),
+        Instruction::Right.into(),
+        zero_cell(),
+        offset_to_insns(offset_from(Positions::PACKET_IS_UDP, Positions::PACKET_LOOP_START)),
+        Item::Comment("end of packet".to_owned(), CommentLevel::INFO),
     ])
 }
 
 fn read_packet_loop() -> Item {
     Item::Sequence(vec![
-        packet_loop_before_check().comment("before check", 180),
+        packet_loop_before_check().comment("before check", CommentLevel::INFO),
         Loop::new(vec![
-            packet_loop_after_check().comment("after check", 180),
-            packet_loop_before_check().comment("before check", 180),
+            packet_loop_after_check().comment("after check", CommentLevel::INFO),
+            packet_loop_before_check().comment("before check", CommentLevel::INFO),
         ])
         .indent()
         .into(),
@@ -318,14 +329,28 @@ impl Positions {
     const PACKET_IP_TOTAL_LENGTH_SCRATCH: usize = Self::PACKET_IP_TOTAL_LENGTH + 1; // 3 cells
 
     const PACKET_IP_PROTOCOL: usize = Self::PACKET_IP_TOTAL_LENGTH_SCRATCH + 3;
+    // Set by `handle_protocol` for the rest of the packet
+    const PACKET_IS_TCP: usize = Self::PACKET_IP_PROTOCOL + 2;
+    const PACKET_IS_UDP: usize = Self::PACKET_IS_TCP + 1;
+    const PACKET_PROTOCOL_SCRATCH: usize = Self::PACKET_IS_UDP + 1;
 
     // As protocol is overwritten
     const PACKET_IP_DEST_START: usize = Self::PACKET_IP_PROTOCOL + 10; // 10-space required for division space
     const PACKET_IP_DEST: usize = Self::PACKET_IP_DEST_START + 3;
 
-    const LIST_HEADSTOP: usize = Self::PACKET_IP_DEST + 1;
+    // Past the cells `output` hard-codes, with room either side for `write_number`
+    const TCP_BYTES_START: usize = Self::PACKET_IP_DEST + 2;
+    const TCP_BYTES: usize = Self::TCP_BYTES_START + (Self::PROTOCOL_BYTES_WIDTH - 1);
+    const UDP_BYTES_START: usize = Self::TCP_BYTES + 2 * Self::PROTOCOL_BYTES_WIDTH + 3;
+    const UDP_BYTES: usize = Self::UDP_BYTES_START + (Self::PROTOCOL_BYTES_WIDTH - 1);
+    const PROTOCOL_BYTES_WIDTH: usize = Self::TRANSPORT_BYTES_WIDTH;
+
+    const LIST_HEADSTOP: usize = Self::UDP_BYTES + 2 * Self::PROTOCOL_BYTES_WIDTH + 2;
     const SECONDARY_IP_STORED_START: usize = Self::LIST_HEADSTOP + 2;
     const LIST_START: usize = Self::LIST_HEADSTOP + ListEntry::WIDTH;
+    // The most distinct destinations the list can hold, as the "and M others" count is a single
+    // cell. Checked as each new destination is added, rather than wrapping around.
+    const LIST_CAPACITY: usize = u8::MAX as usize + 1;
 
     const GREATER_FLAG: usize = Self::LIST_HEADSTOP - 1;
     const GENERAL_COUNT: usize = Self::GREATER_FLAG - 1;
@@ -342,18 +367,14 @@ fn setup_state() -> Item {
     Item::Sequence(vec![
         Item::assert_position(0, "after header discard"),
         offset_to_insns(offset_from(0, Positions::NO_PACKETS_START)),
-        Item::Sequence(vec![
-            Instruction::Dec.into(),
-            Instruction::Dec.into(),
-            Instruction::Right.into(),
-        ])
+        bf!("-->")
         .repeat(Positions::NO_PACKETS_WIDTH + Positions::NO_UDP_WIDTH + Positions::TRANSPORT_BYTES_WIDTH + 2),
         Instruction::Right.into(),
         Instruction::Inc.conv::<Item>().repeat(4),
         Loop::new(vec![
             Instruction::Left.into(),
             Instruction::Left.into(),
-            Loop::new(vec![Instruction::Dec.into(), Instruction::Left.into()]).into(),
+            Loop::new(vec![Instruction::Dec.into(), Instruction::Left.into()]).unbalanced().into(),
             Item::assert_position(Positions::NO_PACKETS_START - 1, "left"),
             Instruction::Right.into(),
             Loop::new(vec![Instruction::Dec.into(), Instruction::Right.into()]).into(),
@@ -369,9 +390,13 @@ fn setup_state() -> Item {
         offset_to_insns(offset_from(Positions::NO_PACKETS + 1, Positions::NO_UDP + 1)),
         zero_cell_up(),
         Item::assert_position(Positions::NO_UDP + 1, "done"),
-        offset_to_insns(offset_from(Positions::NO_UDP + 1, Positions::PACKET_LOOP_START)),
+        offset_to_insns(offset_from(Positions::NO_UDP + 1, Positions::TCP_BYTES_START)),
+        Item::Sequence(vec![Instruction::Dec.conv::<Item>().repeat(10), Instruction::Right.into()]).repeat(Positions::PROTOCOL_BYTES_WIDTH),
+        offset_to_insns(offset_from(Positions::TCP_BYTES + 1, Positions::UDP_BYTES_START)),
+        Item::Sequence(vec![Instruction::Dec.conv::<Item>().repeat(10), Instruction::Right.into()]).repeat(Positions::PROTOCOL_BYTES_WIDTH),
+        offset_to_insns(offset_from(Positions::UDP_BYTES + 1, Positions::PACKET_LOOP_START)),
     ])
-    .comment("setup state", 250)
+    .comment("setup state", CommentLevel::SECTION)
 }
 
 #[allow(dead_code)] // Groups related constants under an unused struct
@@ -389,7 +414,13 @@ impl ListEntry {
     const DATA_WIDTH: usize = 4;
     const DATA_END: usize = Self::DATA_START + Self::DATA_WIDTH - 1;
 
-    const WIDTH: usize = Self::DATA_END + 1;
+    // The maximum finder's round, i.e. the count every marked entry has reached so far, which
+    // `COUNT` is compared against in the space that follows
+    const ROUND: usize = Self::DATA_END + 1;
+    const COMPARE: usize = Self::ROUND + 1;
+    const GREATER: usize = Self::COMPARE + DecimalCompare::<1>::GREATER;
+
+    const WIDTH: usize = Self::COMPARE + DecimalCompare::<1>::LESS + 2;
 }
 
 fn append_to_list() -> Item {
@@ -420,6 +451,7 @@ fn append_to_list() -> Item {
                     insn.into(),
                     offset_to_insns((ListEntry::WIDTH - ListEntry::DATA_START - offset) as _),
                 ])
+                .unbalanced()
                 .into(),
                 offset_to_insns(-(ListEntry::WIDTH as isize)),
                 Loop::new(vec![offset_to_insns(-(ListEntry::WIDTH as isize))]).into(),
@@ -428,7 +460,7 @@ fn append_to_list() -> Item {
             ])
             .indent()
             .conv::<Item>()
-            .comment(format!("[re]distribute {offset}"), 80),
+            .comment(format!("[re]distribute {offset}"), CommentLevel::TRACE),
         ])
     }
 
@@ -449,7 +481,7 @@ fn append_to_list() -> Item {
             offset_to_insns(offset_from(ListEntry::DATA_START + offset, ListEntry::MARKED_FLAG)),
             drain(&[1], true),
             offset_to_insns(offset_from(ListEntry::MARKED_FLAG, ListEntry::SCRATCH + 1)),
-            drain(&[offset_from(ListEntry::SCRATCH + 1, ListEntry::DATA_START + offset)], true),
+            move_cell(offset_from(ListEntry::SCRATCH + 1, ListEntry::DATA_START + offset)),
             offset_to_insns(offset_from(ListEntry::SCRATCH + 1, ListEntry::DATA_START + offset)),
             Item::assert_marker_offset(
                 "current zero target",
@@ -478,7 +510,7 @@ fn append_to_list() -> Item {
             .into(),
             offset_to_insns(offset_from(Positions::PACKET_IP_DEST_START + offset, Positions::LIST_HEADSTOP)),
         ])
-        .comment(format!("copy over {{offset={offset}}}"), 80)
+        .comment(format!("copy over {{offset={offset}}}"), CommentLevel::TRACE)
     }
 
     Item::Sequence(vec![
@@ -502,8 +534,8 @@ fn append_to_list() -> Item {
             accumulate_zero(2),
             Instruction::Right.into(),
             accumulate_zero(3),
-            Item::assert_marker_offset("current zero target", (ListEntry::WIDTH - 1) as _, "end"),
-            offset_to_insns(offset_from(ListEntry::WIDTH - 1, ListEntry::SCRATCH)),
+            Item::assert_marker_offset("current zero target", ListEntry::DATA_END as _, "end"),
+            offset_to_insns(offset_from(ListEntry::DATA_END, ListEntry::SCRATCH)),
             Item::remove_marker("current zero target"),
             Instruction::Right.into(),
             Instruction::Inc.into(),
@@ -529,12 +561,12 @@ fn append_to_list() -> Item {
             ])
             .indent()
             .conv::<Item>()
-            .comment("if zero (IP match)", 120),
+            .comment("if zero (IP match)", CommentLevel::DEBUG),
             offset_to_insns(offset_from(ListEntry::SCRATCH + 1, ListEntry::WIDTH)),
         ])
         .indent()
         .conv::<Item>()
-        .comment("check each known IP for a match", 130),
+        .comment("check each known IP for a match", CommentLevel::DEBUG),
         offset_to_insns(-(ListEntry::WIDTH as isize)),
         Loop::new(vec![
             Instruction::Right.into(),
@@ -580,11 +612,11 @@ fn append_to_list() -> Item {
             Instruction::Dec.into(),
             offset_to_insns(offset_from(Positions::LIST_HEADSTOP + 2, Positions::PACKET_IP_DEST_START)),
             Item::Sequence(vec![zero_cell(), Instruction::Right.into()]).repeat(4),
-            Instruction::Right.into(),
+            offset_to_insns(offset_from(Positions::PACKET_IP_DEST_START + 4, Positions::LIST_HEADSTOP + 1)),
         ])
         .indent()
         .conv::<Item>()
-        .comment("if mark (found)", 120),
+        .comment("if mark (found)", CommentLevel::DEBUG),
         Item::assert_position(Positions::LIST_HEADSTOP + 1, "mark found"),
         Instruction::Right.into(),
         Loop::new(vec![
@@ -592,6 +624,16 @@ fn append_to_list() -> Item {
             offset_to_insns(offset_from(Positions::LIST_HEADSTOP + 2, Positions::LIST_START)),
             Loop::new(vec![offset_to_insns(ListEntry::WIDTH as _)]).into(),
             Item::add_marker("new entry"),
+            Item::named_custom("check list capacity", |tape, pointer, _| {
+                let index = (pointer - Positions::LIST_START) / ListEntry::WIDTH;
+                if index >= Positions::LIST_CAPACITY {
+                    bail!(
+                        "destination list is full, it can only hold {} distinct destinations\n{tape}",
+                        Positions::LIST_CAPACITY
+                    );
+                }
+                Ok(())
+            }),
             Instruction::Inc.into(),
             // Yes, using the 0 count to be 1 occurrence *would* work, and would let us
             // show counts of up to 256 instead of 255, but it makes writing the
@@ -611,461 +653,66 @@ fn append_to_list() -> Item {
         ])
         .indent()
         .conv::<Item>()
-        .comment("else (new)", 120),
+        .comment("else (new)", CommentLevel::DEBUG),
         Item::assert_position(Positions::LIST_HEADSTOP + 2, "mark not found"),
     ])
 }
 
-// Positioned on the first cell of the number
-// Cannot be called on cell 0
-fn display_decimal(width: usize, extra_gap: usize) -> Item {
-    let mark = "display start";
-    Item::Sequence(vec![
-        Item::add_marker(mark),
-        offset_to_insns(2 * width as isize + extra_gap as isize),
-        Instruction::Right.into(),
-        Instruction::Inc.conv::<Item>().repeat(8),
-        Loop::new(vec![
-            Instruction::Dec.into(),
-            Instruction::Left.into(),
-            Instruction::Inc.conv::<Item>().repeat(6),
-            Instruction::Right.into(),
-        ])
-        .into(),
-        Instruction::Left.into(),
-        Loop::new(vec![
-            Instruction::Dec.into(),
-            Item::Sequence(vec![Instruction::Right.into(), Instruction::Inc.into()]).repeat(width),
-            Instruction::Left.conv::<Item>().repeat(width),
-        ])
-        .into(),
-        Item::assert_marker_offset(mark, 2 * width as isize + extra_gap as isize, "init output end"),
-        offset_to_insns(offset_from(2 * width + extra_gap, 2 * width)),
-        Instruction::Dec.into(),
-        offset_to_insns(offset_from(2 * width, width - 1)),
+fn output() -> Item {
+    // Uses the current cell and the one to its right, which must be zero
+    fn write_text(text: &str) -> Item {
+        Item::emit_str(text).comment(format!("write {text:?}"), CommentLevel::INFO)
+    }
+
+    // Prints a `width`-cell number, then moves it along past the scratch `write_number` used, where
+    // the rest of `output` expects it
+    fn write_number_moved(width: usize) -> Item {
         Item::Sequence(vec![
-            Loop::new(vec![
-                Instruction::Dec.into(),
-                offset_to_insns(width as isize),
-                zero_cell(),
-                Instruction::Inc.into(),
-                offset_to_insns(width as isize + 1 + extra_gap as isize),
-                Instruction::Inc.into(),
-                offset_to_insns(-(2 * width as isize + 1 + extra_gap as isize)),
-            ])
-            .into(),
-            Instruction::Left.into(),
+            write_number(width),
+            Item::Sequence(vec![move_cell(2 * width as isize + 1), Instruction::Right.into()]).repeat(width),
+            offset_to_insns(-(width as isize)),
         ])
-        .repeat(width)
-        .comment("leading zeros filter", 120),
-        Item::assert_marker_offset(mark, -1, "after transport bytes leading zeros"),
-        find_non_zero_cell_right(),
-        Instruction::Left.into(),
-        Instruction::Inc.into(),
-        Instruction::Right.into(),
-        Instruction::Inc.into(),
-        Loop::new(vec![
-            Loop::new(vec![
-                zero_cell(),
-                offset_to_insns(width as isize + 1 + extra_gap as isize),
-                Instruction::Output.into(),
-                offset_to_insns(-(width as isize + 1 + extra_gap as isize)),
-            ])
-            .into(),
-            Instruction::Right.into(),
-            Instruction::Inc.into(),
-        ])
-        .into(),
+    }
+
+    // Clears the `width` cells from the current one, and returns
+    fn zero_number(width: usize) -> Item {
         Item::Sequence(vec![
-            Item::Sequence(vec![Instruction::Left.into(), zero_cell()]).repeat(width + 1),
-            find_non_zero_cell_right(),
-            Item::assert_marker_offset(
-                mark,
-                2 * width as isize + 1 + extra_gap as isize,
-                "begin restore transport bytes",
-            ),
-            Instruction::Left.conv::<Item>().repeat(2),
-            Instruction::Inc.conv::<Item>().repeat(8),
-            Loop::new(vec![
-                Instruction::Dec.into(),
-                Instruction::Right.into(),
-                Instruction::Inc.conv::<Item>().repeat(6),
-                Instruction::Left.into(),
-            ])
-            .into(),
-            Instruction::Right.into(),
-            Loop::new(vec![
-                Instruction::Dec.into(),
-                Item::Sequence(vec![Instruction::Right.into(), Instruction::Dec.into()]).repeat(width),
-                Instruction::Left.conv::<Item>().repeat(width),
-            ])
-            .into(),
-            Instruction::Left.conv::<Item>().repeat(2 * width + extra_gap),
+            Item::Sequence(vec![zero_cell(), Instruction::Right.into()]).repeat(width),
+            offset_to_insns(-(width as isize)),
         ])
-        .comment("decimal cleanup", 120),
-        Item::assert_marker_offset(mark, 0, "decimal reset"),
-        Item::remove_marker(mark),
-    ])
-    .comment(format!("display decimal {{width={width}}}"), 180)
-}
-
-fn output() -> Item {
-    #[derive(Debug)]
-    enum Text {
-        TransportLevelData,
-        BytesNewline,
-        UDP,
-        TCPNewline,
-        BytesPerPacket,
-        MostPopular,
-        DestinationWas,
-        DestinationsWere,
-        And,
-        Other,
-        With,
-        Packet,
-        Each,
-        Newline,
-        AverageOf,
     }
 
-    fn write_text(text: Text) -> Item {
-        // Text output code generated with https://tnu.me/brainfuck/generator
-        let marker = format!("write text {text:?}");
-        let v = match text {
-            Text::TransportLevelData => {
-                vec![
-                    Item::parse(
-                        "+++++++++[>+++++++++>++++++++++++>+++++++++++>++++>++++++++>+++++>++++++\
-                        <<<<<<<-]>+++.>+++.+++++.>--.<--------.>>----.>+.+++++++.>.<<<<.>++++.<+++++\
-                        +++++.>.+++++++.>.<--------.---.<--.>.>>>>++++.<<<.",
-                    )
-                    .expect("should be valid")
-                    .comment("write \"Total IP-level data: \"", 220),
-                    Item::assert_marker_offset(marker.clone(), 4, "after text write"),
-                    Instruction::Right.conv::<Item>().repeat(2),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::BytesNewline => {
-                vec![
-                    Item::parse("++++++++[>++++>++++++++++++>+++++++++++++++>+<<<<-]>.>++.>+.-----.<+++.>-.>++.")
-                        .expect("should be valid")
-                        .comment("write \" bytes\\n\"", 220),
-                    Item::assert_marker_offset(marker.clone(), 4, "after text write"),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::UDP => {
-                vec![
-                    Item::parse("+++++++[>+++++>++++++++++++>++++++++++>++++++<<<<-]>---.>+.>--.<-----.>>++.<<<.")
-                        .expect("should be valid")
-                        .comment("write \" UDP, \"", 220),
-                    Item::assert_marker_offset(marker.clone(), 1, "after text write"),
-                    Loop::new(vec![zero_cell(), Instruction::Right.into()]).into(),
-                    offset_to_insns(-5),
-                ]
-            }
-            Text::TCPNewline => {
-                vec![
-                    Item::parse("+++++++[>+++++>++++++++++++>++++++++++>+<<<<-]>---.>.>---.<----.>>+++.")
-                        .expect("should be valid")
-                        .comment("write \" TCP\\n\"", 220),
-                    Item::assert_marker_offset(marker.clone(), 4, "after text write"),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::BytesPerPacket => {
-                vec![
-                    Item::parse(
-                        "+++++++[>+++++>++++++++++++++>+++++++++++++++++>+++++++>+<<<<<-]>---.>.>\
-                    ++.-----.<+++.>-.>--.<---.<----.++.>-----.<++.>+++++++++.>>+++.",
-                    )
-                    .expect("should be valid")
-                    .comment("write \" bytes/packet\\n\"", 220),
-                    Item::assert_marker_offset(marker.clone(), 5, "after text write"),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::MostPopular => {
-                vec![
-                    Item::parse(
-                        "++++++++++[>++++++++>+++++++++++>+++>++++++++++>++++++++++<<<<<-]>---.\
-                        >+.++++.+.>++.<----.-.+.+++++.---------.>>---.<<++++++.>.>>.+.<<<+.+.>>>++++\
-                        .+++++.<.<<.>>>-----.++++++.-.",
-                    )
-                    .expect("should be valid")
-                    .comment("write \"Most popular destination\"", 220),
-                    Item::assert_marker_offset(marker.clone(), 5, "after text write"),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::DestinationWas => {
-                vec![
-                    Item::parse("++++++++++[>+++>++++++++++++>++++++++++<<<-]>++.>-.>---.<----.<.")
-                        .expect("should be valid")
-                        .comment("write \" was \"", 220),
-                    Item::assert_marker_offset(marker.clone(), 1, "after text write"),
-                    offset_to_insns(2),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::DestinationsWere => {
-                vec![
-                    Item::parse(
-                        "++++++++++[>++++++++++++>+++>++++++++++<<<-]>-----.>++.<++++.\
-                    >>+.<<-----.>>.<.",
-                    )
-                    .expect("should be valid")
-                    .comment("write \"s were \"", 220),
-                    Item::assert_marker_offset(marker.clone(), 2, "after text write"),
-                    offset_to_insns(1),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::And => {
-                vec![
-                    Item::parse("++++++++++[>+++>++++++++++>+++++++++++<<<-]>++.>---.>.<+++.<.")
-                        .expect("should be valid")
-                        .comment("write \" and \"", 220),
-                    Item::assert_marker_offset(marker.clone(), 1, "after text write"),
-                    offset_to_insns(2),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::Other => {
-                vec![
-                    Item::parse("++++++++++[>+++>+++++++++++>++++++++++<<<-]>++.>+.+++++.>++++.---.<--.")
-                        .expect("should be valid")
-                        .comment("write \" other\"", 220),
-                    Item::assert_marker_offset(marker.clone(), 2, "after text write"),
-                    offset_to_insns(1),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::With => {
-                vec![
-                    Item::parse("++++++++++[>+++>++++++++++++>+++++++++++<<<-]>++.>-.>-----.<---.>-.<<.")
-                        .expect("should be valid")
-                        .comment("write \" with \"", 220),
-                    Item::assert_marker_offset(marker.clone(), 1, "after text write"),
-                    offset_to_insns(2),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::Packet => {
-                vec![
-                    Item::parse("++++++++++[>+++>+++++++++++>++++++++++<<<-]>++.>++.>---.++.<-----.>++.<+++++++++.")
-                        .expect("should be valid")
-                        .comment("write \" packet\"", 220),
-                    Item::assert_marker_offset(marker.clone(), 2, "after text write"),
-                    offset_to_insns(1),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::Each => {
-                vec![
-                    Item::parse("++++++++[>++++>+++++++++++++<<-]>.>---.----.++.+++++.")
-                        .expect("should be valid")
-                        .comment("write \" each\"", 220),
-                    Item::assert_marker_offset(marker.clone(), 2, "after text write"),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::Newline => {
-                vec![
-                    Item::parse("+++[>+++<-]>+.").expect("should be valid").comment("write \"\\n\"", 220),
-                    Item::assert_marker_offset(marker.clone(), 1, "after text write"),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-            Text::AverageOf => {
-                vec![
-                    Item::parse(
-                        "+++++++++[>+++++++>+++++++++++++>+++++++++++>++++<<<<-]>++.>+.>+\
-                    +.<----.>----.++++++.--.>----.<<---.>+.>.",
-                    )
-                    .expect("should be valid")
-                    .comment("write \"Average of \"", 220),
-                    Item::assert_marker_offset(marker.clone(), 4, "after text write"),
-                    Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
-                ]
-            }
-        };
+    // Prints and then clears one of the per-protocol byte counters
+    fn protocol_bytes(text: &str) -> Item {
+        const W: usize = Positions::PROTOCOL_BYTES_WIDTH;
+
         Item::Sequence(vec![
-            Item::add_marker(marker.clone()),
-            Item::Sequence(v),
-            Item::assert_marker_offset(marker.clone(), 0, "after text cleanup"),
-            Item::remove_marker(marker),
+            Item::Sequence(vec![Instruction::Inc.conv::<Item>().repeat(10), Instruction::Right.into()]).repeat(W),
+            offset_to_insns(-(W as isize)),
+            write_number(W),
+            zero_number(W),
+            write_text(text),
         ])
     }
 
     fn divide() -> Item {
-        fn new_zero_check(temp_copy: isize, accumulator: isize) -> Item {
-            Item::Sequence(vec![
-                Loop::new(vec![
-                    Instruction::Dec.into(),
-                    offset_to_insns(temp_copy),
-                    Instruction::Inc.into(),
-                    offset_to_insns(-temp_copy),
-                    offset_to_insns(accumulator),
-                    Instruction::Inc.into(),
-                    offset_to_insns(-accumulator),
-                ])
-                .into(),
-                offset_to_insns(temp_copy),
-                drain(&[-temp_copy], true),
-                offset_to_insns(-temp_copy),
-            ])
-        }
-
-        // On the last cell of the number
-        fn zero_check_number(width: usize, temp_copy: isize, accumulator: isize) -> Item {
-            let s = (0..width)
-                .flat_map(|i| [new_zero_check(temp_copy + i as isize, accumulator + i as isize), Instruction::Left.into()])
-                .collect();
-
-            Item::Sequence(vec![
-                offset_to_insns(accumulator),
-                zero_cell(),
-                offset_to_insns(-accumulator),
-                Item::Sequence(s),
-                offset_to_insns(width as _),
-            ])
-            .comment(format!("zero check number {{width={width}}}"), 120)
-        }
-
-        const ZC: usize = 0;
-        const SC: usize = 1;
-
-        /*
-        N - number (decimal 9)
-        D - divisor (decimal 7)
-        T - temporary storage (decimal 7)
-        Q - quotient (decimal 9)
-         */
-
-        const NW: usize = Positions::TRANSPORT_BYTES_WIDTH;
-        const N: usize = SC + 2 + NW - 1; // = 11
-        const N0: usize = N + 1;
-
-        const DW: usize = Positions::NO_PACKETS_WIDTH;
-        const D: usize = N0 + DW; // = 19
-        const D0: usize = D + 1;
-
-        const TW: usize = DW;
-        const T: usize = D0 + TW;
-        const T0: usize = T + 1;
-
-        const QW: usize = NW;
-        const Q: usize = T0 + QW;
-        const Q0: usize = Q + 1;
+        type Divide = DecimalDivide<{ Positions::TRANSPORT_BYTES_WIDTH }, { Positions::NO_PACKETS_WIDTH }>;
+        const QW: usize = Positions::TRANSPORT_BYTES_WIDTH;
+        const Q: usize = Divide::QUOTIENT;
 
         Item::Sequence(vec![
             Item::assert_position(0, "before division"),
-            offset_to_insns(offset_from(0, N)),
+            offset_to_insns(offset_from(0, Divide::DIVIDEND)),
             Item::assert_marker_offset("divide N", 0, "N correctly positioned"),
-            offset_to_insns(offset_from(N, D)),
+            offset_to_insns(offset_from(Divide::DIVIDEND, Divide::DIVISOR)),
             Item::assert_marker_offset("divide D", 0, "D correctly positioned"),
-            offset_to_insns(offset_from(D, 0)),
-            offset_to_insns(offset_from(0, T0)),
-            Instruction::Inc.conv::<Item>().repeat(10),
-            Loop::new(vec![
-                Instruction::Dec.into(),
-                Item::Sequence(vec![Instruction::Left.into(), Instruction::Dec.into()]).repeat(TW),
-                Instruction::Right.conv::<Item>().repeat(TW),
-            ])
-            .into(),
-            Item::assert_position(T0, "after init"),
-            offset_to_insns(offset_from(T0, 0)),
-            offset_to_insns(offset_from(0, Q0)),
-            Instruction::Inc.conv::<Item>().repeat(10),
-            Loop::new(vec![
-                Instruction::Dec.into(),
-                Item::Sequence(vec![Instruction::Left.into(), Instruction::Dec.into()]).repeat(QW),
-                offset_to_insns(QW as _),
-            ])
-            .into(),
-            Item::assert_position(Q0, "Q setup"),
-            offset_to_insns(offset_from(Q0, 0)),
-            // Setup complete, at cell 0
-            offset_to_insns(offset_from(0, N)),
-            zero_check_number(NW, offset_from(N, SC), offset_from(N, ZC)),
-            Item::assert_position(N, "still here"),
-            offset_to_insns(offset_from(N, ZC)),
-            Loop::new(vec![
-                zero_cell(),
-                offset_to_insns(offset_from(ZC, N)),
-                operate::<DecimalSub<NW>>(offset_from(N, ZC)),
-                Item::assert_position(N, "after N subtract"),
-                offset_to_insns(offset_from(N, ZC)),
-                zero_cell(),
-                offset_to_insns(offset_from(ZC, D)),
-                operate::<DecimalSub<DW>>(offset_from(D, ZC)),
-                Item::assert_position(D, "after D subtract"),
-                zero_check_number(DW, offset_from(D, SC), offset_from(D, ZC)),
-                offset_to_insns(offset_from(D, ZC)),
-                drain(&[offset_from(ZC, N0)], true),
-                offset_to_insns(offset_from(ZC, T)),
-                operate::<DecimalAdd<TW>>(offset_from(T, ZC)),
-                Item::assert_position(T, "after T add"),
-                offset_to_insns(offset_from(T, N0)),
-                drain(&[offset_from(N0, ZC)], true),
-                offset_to_insns(offset_from(N0, ZC)),
-                Instruction::Right.into(),
-                zero_cell(),
-                Instruction::Inc.into(),
-                Instruction::Left.into(),
-                // If nonzero (i.e. d != 0)
-                Loop::new(vec![
-                    zero_cell(),
-                    Instruction::Right.into(),
-                    zero_cell(),
-                    Instruction::Left.into(),
-                ])
-                .into(),
-                Instruction::Right.into(),
-                Item::assert_position(ZC + 1, "before else"),
-                // Else (i.e. d == 0)
-                Loop::new(vec![
-                    zero_cell(),
-                    offset_to_insns(offset_from(ZC + 1, T)),
-                    Item::Sequence(vec![drain(&[offset_from(T, D)], true), Instruction::Left.into()]).repeat(TW),
-                    Item::assert_position(D + 1, "after restore D"),
-                    offset_to_insns(offset_from(D + 1, T0)),
-                    Instruction::Inc.conv::<Item>().repeat(10),
-                    Loop::new(vec![
-                        Instruction::Dec.into(),
-                        Item::Sequence(vec![Instruction::Left.into(), Instruction::Dec.into()]).repeat(TW),
-                        Instruction::Left.into(),
-                        Item::Sequence(vec![Instruction::Left.into(), Instruction::Inc.into()]).repeat(DW),
-                        Instruction::Right.conv::<Item>().repeat(TW + DW + 1),
-                    ])
-                    .into(),
-                    Item::assert_position(T0, "after unreset T+D"),
-                    offset_to_insns(offset_from(T0, Q)),
-                    operate::<DecimalAdd<QW>>(offset_from(Q, ZC)),
-                    Item::assert_position(Q, "after increment Q"),
-                    offset_to_insns(offset_from(Q, ZC + 1)),
-                ])
-                .into(),
-                offset_to_insns(offset_from(ZC + 1, N)),
-                zero_check_number(NW, offset_from(N, SC), offset_from(N, ZC)),
-                Item::assert_position(N, "before loop"),
-                offset_to_insns(offset_from(N, ZC)),
-            ])
-            .into(),
-            offset_to_insns(offset_from(ZC, Q0)),
-            Instruction::Inc.conv::<Item>().repeat(10),
-            Loop::new(vec![
-                Instruction::Dec.into(),
-                Item::Sequence(vec![Instruction::Left.into(), Instruction::Inc.into()]).repeat(QW),
-                offset_to_insns(QW as _),
-            ])
-            .into(),
-            Item::assert_position(Q0, "Q desetup"),
+            offset_to_insns(offset_from(Divide::DIVISOR, 0)),
+            Divide::divide(),
+            Item::assert_position(0, "after division"),
+            offset_to_insns(offset_from(0, Q + 1)),
             offset_to_insns(-(QW as isize)),
-            display_decimal(QW, 0),
+            write_number(QW),
+            zero_number(QW),
             Item::assert_position(Q - QW + 1, "after division"),
             offset_to_insns(offset_from(Q - QW + 1, 0)),
         ])
@@ -1079,7 +726,10 @@ fn output() -> Item {
         let current_marker = "current item";
         Item::Sequence(vec![
             Item::assert_position(Positions::LIST_START, pass_name),
-            Item::custom(move |_, _, _| brk2.store(false, Ordering::SeqCst)),
+            Item::custom(move |_, _, _| {
+                brk2.store(false, Ordering::SeqCst);
+                Ok(())
+            }),
             Loop::new(vec![
                 Item::add_marker(current_marker),
                 perform,
@@ -1092,6 +742,7 @@ fn output() -> Item {
                 }),
                 Item::remove_marker(current_marker),
             ])
+            .unbalanced()
             .into(),
             Item::custom(move |tape, position, markers| {
                 if brk3.load(Ordering::SeqCst) {
@@ -1105,19 +756,7 @@ fn output() -> Item {
             Item::assert_position(Positions::LIST_HEADSTOP, pass_name),
             offset_to_insns(offset_from(Positions::LIST_HEADSTOP, Positions::LIST_START)),
         ])
-        .comment(format!("list pass: {pass_name}"), 180)
-    }
-
-    // Not useful for the wider numbers as it relies on the number being single-cell
-    fn print_decimal_cell() -> Item {
-        // Taken from https://esolangs.org/wiki/Brainfuck_algorithms#Print_value_of_cell_x_as_number_(8-bit)
-        // I'm not 100% certain how this works
-        Item::parse(
-            ">>++++++++++<<[->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]>>[-]>>>++++++++++<[->-[>+>>]>\
-            [+[-<+>]>+>>]<<<<<]>[-]>>[>++++++[-<++++++++>]<.<<+>+>[-]]<[<[->-<]++++++[->++++++++<]>.[-]\
-            ]<<++++++[-<++++++++>]<.[-]<<[-<+>]<",
-        )
-        .expect("should be valid")
+        .comment(format!("list pass: {pass_name}"), CommentLevel::INFO)
     }
 
     fn pull_back(offset: usize) -> Item {
@@ -1146,16 +785,10 @@ fn output() -> Item {
 
     Item::Sequence(vec![
         Item::assert_position(Positions::PACKET_LOOP_START, "after loop"),
-        Item::Comment("begin output".to_owned(), 240),
+        Item::Comment("begin output".to_owned(), CommentLevel::SECTION),
         offset_to_insns(offset_from(Positions::PACKET_LOOP_START, Positions::SCRATCH_SPACE - 1)),
         Instruction::Inc.conv::<Item>().repeat(5),
-        Loop::new(vec![
-            Instruction::Dec.into(),
-            Instruction::Right.into(),
-            Instruction::Dec.conv::<Item>().repeat(2),
-            Instruction::Left.into(),
-        ])
-        .into(),
+        drain_scaled(&[(1, 2)], false),
         Instruction::Right.into(),
         Item::assert_position(Positions::SCRATCH_SPACE, "begin decimal conversion"),
         offset_to_insns(offset_from(Positions::SCRATCH_SPACE, Positions::NO_PACKETS + 1)),
@@ -1166,7 +799,7 @@ fn output() -> Item {
         Loop::new(vec![
             Instruction::Dec.into(),
             Instruction::Right.into(),
-            Loop::new(vec![Instruction::Dec.into(), Instruction::Right.into()]).into(),
+            Loop::new(vec![Instruction::Dec.into(), Instruction::Right.into()]).unbalanced().into(),
             Item::assert_position(Positions::TRANSPORT_BYTES + 1, "right moving"),
             offset_to_insns(offset_from(Positions::TRANSPORT_BYTES + 1, Positions::SCRATCH_SPACE)),
         ])
@@ -1176,25 +809,16 @@ fn output() -> Item {
         offset_to_insns(offset_from(Positions::NO_PACKETS + 1, Positions::NO_UDP + 1)),
         zero_cell(),
         offset_to_insns(offset_from(Positions::NO_UDP + 1, Positions::TRANSPORT_BYTES + 1)),
-        Item::Sequence(vec![
-            // This requires _7_ bytes of space, but we only have 6!
-            // So we go in search of some convenient space to use...
-            offset_to_insns(offset_from(Positions::TRANSPORT_BYTES + 1, Positions::LIST_START)),
-            Loop::new(vec![offset_to_insns(ListEntry::WIDTH as _)]).into(),
-            write_text(Text::TransportLevelData),
-            offset_to_insns(-(ListEntry::WIDTH as isize)),
-            Loop::new(vec![offset_to_insns(-(ListEntry::WIDTH as isize))]).into(),
-            Item::assert_position(Positions::LIST_HEADSTOP, "return to headstop"),
-            offset_to_insns(offset_from(Positions::LIST_HEADSTOP, Positions::TRANSPORT_BYTES + 1)),
-        ]),
+        // The gap after the number, and the packet loop's (now zero) flag
+        write_text("Total IP-level data: "),
         Item::assert_position(Positions::TRANSPORT_BYTES + 1, "after first output"),
         offset_to_insns(offset_from(Positions::TRANSPORT_BYTES + 1, Positions::TRANSPORT_BYTES_START)),
-        display_decimal(Positions::TRANSPORT_BYTES_WIDTH, 0),
-        write_text(Text::BytesNewline),
+        write_number_moved(Positions::TRANSPORT_BYTES_WIDTH),
+        write_text(" bytes\n"),
         Item::assert_position(Positions::TRANSPORT_BYTES_START, "still here"),
         offset_to_insns(offset_from(Positions::TRANSPORT_BYTES_START, Positions::NO_UDP_START)),
-        display_decimal(Positions::NO_UDP_WIDTH, 0),
-        write_text(Text::UDP),
+        write_number_moved(Positions::NO_UDP_WIDTH),
+        write_text(" UDP, "),
         offset_to_insns(offset_from(Positions::NO_UDP_START, Positions::NO_PACKETS_START)),
         Item::Sequence(vec![
             drain(&[-4, 4 + Positions::NO_PACKETS_WIDTH as isize], true),
@@ -1202,7 +826,7 @@ fn output() -> Item {
         ])
         .repeat(Positions::NO_PACKETS_WIDTH),
         offset_to_insns(Positions::NO_PACKETS_WIDTH as isize),
-        Item::Sequence(vec![Instruction::Right.into(), Instruction::Inc.into()]).repeat(Positions::NO_PACKETS_WIDTH),
+        bf!(">+").repeat(Positions::NO_PACKETS_WIDTH),
         Loop::new(vec![
             Instruction::Dec.into(),
             offset_to_insns(1 + Positions::NO_PACKETS_WIDTH as isize),
@@ -1214,7 +838,7 @@ fn output() -> Item {
             ])
             .indent()
             .conv::<Item>()
-            .comment("subtraction level", 140),
+            .comment("subtraction level", CommentLevel::DEBUG),
             offset_to_insns(-1 - Positions::NO_PACKETS_WIDTH as isize),
             Item::Sequence(vec![
                 // The cell to the right of the subtraction cell needs to be zero, so we have to move
@@ -1225,11 +849,12 @@ fn output() -> Item {
                 Item::custom(|tape, position, markers| {
                     static FIRST_TIME: AtomicBool = AtomicBool::new(true);
                     if FIRST_TIME.swap(false, Ordering::SeqCst) {
-                        Item::add_marker("stored").run(tape, position, markers)
+                        Item::add_marker("stored").run(tape, position, markers)?;
                     }
+                    Ok(())
                 }),
                 offset_to_insns(-(2 * Positions::NO_PACKETS_WIDTH as isize + 3)),
-                drain(&[2 * Positions::NO_PACKETS_WIDTH as isize + 3], true),
+                move_cell(2 * Positions::NO_PACKETS_WIDTH as isize + 3),
                 offset_to_insns(1 + Positions::NO_PACKETS_WIDTH as isize),
                 Item::assert_marker_offset("return here", 0, "returned"),
                 Item::remove_marker("return here"),
@@ -1238,12 +863,12 @@ fn output() -> Item {
         ])
         .indent()
         .conv::<Item>()
-        .comment("subtract UDP from total packets", 200),
+        .comment("subtract UDP from total packets", CommentLevel::SECTION),
         Item::Sequence(vec![
             offset_to_insns(10),
             Item::assert_marker_offset("stored", -6, "move TCP"),
             Item::Sequence(vec![
-                drain(&[-(2 * Positions::NO_PACKETS_WIDTH as isize + 3)], true),
+                move_cell(-(2 * Positions::NO_PACKETS_WIDTH as isize + 3)),
                 Instruction::Right.into(),
             ])
             .repeat(Positions::NO_PACKETS_WIDTH),
@@ -1251,13 +876,21 @@ fn output() -> Item {
             offset_to_insns(-24),
         ]),
         Item::assert_position(11, "TCP packets"),
-        display_decimal(Positions::NO_PACKETS_WIDTH, 0),
-        write_text(Text::TCPNewline),
+        write_number_moved(Positions::NO_PACKETS_WIDTH),
+        write_text(" TCP\n"),
+        Item::Sequence(vec![
+            offset_to_insns(offset_from(11, Positions::TCP_BYTES_START)),
+            protocol_bytes(" bytes over TCP, "),
+            offset_to_insns(offset_from(Positions::TCP_BYTES_START, Positions::UDP_BYTES_START)),
+            protocol_bytes(" bytes over UDP\n"),
+            offset_to_insns(offset_from(Positions::UDP_BYTES_START, 11)),
+        ])
+        .comment("bytes by protocol", CommentLevel::SECTION),
         Item::assert_position(11, "before clear subtraction"),
         offset_to_insns(14),
         Item::Sequence(vec![Instruction::Right.into(), zero_cell()]).repeat(Positions::NO_PACKETS_WIDTH),
         Item::assert_position(32, "after clear subtraction"),
-        write_text(Text::AverageOf),
+        write_text("Average of "),
         // Prepare division
         offset_to_insns(offset_from(32, 6)),
         Item::Sequence(vec![Instruction::Right.into(), zero_cell()]).repeat(48 - 7 - 9),
@@ -1281,7 +914,7 @@ fn output() -> Item {
         offset_to_insns(offset_from(11, 0)),
         divide(),
         Item::assert_position(0, "after division"),
-        write_text(Text::BytesPerPacket),
+        write_text(" bytes/packet\n"),
         // This isn't efficient - most of the cells are *already* guaranteed to be 0, but at this point
         // I'm not going to spend time figuring out which specific cells need zeroing.
         Item::Sequence(vec![zero_cell(), Instruction::Right.into()]).repeat(Positions::LIST_START),
@@ -1294,6 +927,7 @@ fn output() -> Item {
             Instruction::Inc.into(),
             offset_to_insns(offset_from(ListEntry::MARKED_FLAG, ListEntry::WIDTH)),
         ])
+        .unbalanced()
         .into(),
         Item::add_marker("list end"),
         Instruction::Left.conv::<Item>().repeat(ListEntry::WIDTH),
@@ -1306,98 +940,49 @@ fn output() -> Item {
             offset_to_insns(offset_from(Positions::LIST_LOOP_FLAG, Positions::LIST_START)),
             // Yes, some/most/all of these passes *could* be collapsed into one
             // Given that this is more understandable: no, they will be kept separate
-            list_pass("zero check", |_| {
-                // Set `scratch1` to `count`==0
-                Item::Sequence(vec![
-                    offset_to_insns(offset_from(ListEntry::EXIST_FLAG, ListEntry::COUNT)),
-                    Instruction::Left.into(),
-                    Instruction::Left.into(),
-                    Instruction::Inc.into(),
-                    Instruction::Right.into(),
-                    Instruction::Right.into(),
-                    Loop::new(vec![
-                        Instruction::Dec.into(),
-                        Instruction::Left.into(),
-                        Instruction::Inc.into(),
-                        Instruction::Left.into(),
-                        zero_cell(),
-                        Instruction::Right.into(),
-                        Instruction::Right.into(),
-                    ])
-                    .into(),
-                    Instruction::Left.into(),
-                    drain(&[1], true),
-                    Instruction::Right.into(),
-                    offset_to_insns(offset_from(ListEntry::COUNT, ListEntry::WIDTH)),
-                ])
-            }),
             list_pass("find greater items", |_| {
-                Item::Sequence(vec![
-                    Instruction::Right.into(),
-                    Instruction::Right.into(),
-                    Instruction::Right.into(),
-                    Instruction::Inc.into(),
-                    Instruction::Left.into(),
-                    Loop::new(vec![
-                        zero_cell(),
-                        Instruction::Right.into(),
-                        Instruction::Dec.into(),
-                        Instruction::Left.into(),
+                type Compare = DecimalCompare<1>;
+
+                // Copies the cell at `from` to `to`, using the (empty) scratch cell
+                fn copy(from: usize, to: usize) -> Item {
+                    Item::Sequence(vec![
+                        offset_to_insns(offset_from(ListEntry::EXIST_FLAG, from)),
+                        drain(&[offset_from(from, ListEntry::SCRATCH), offset_from(ListEntry::SCRATCH, to)], true),
+                        offset_to_insns(offset_from(from, ListEntry::SCRATCH)),
+                        move_cell(offset_from(ListEntry::SCRATCH, from)),
+                        offset_to_insns(offset_from(ListEntry::SCRATCH, ListEntry::EXIST_FLAG)),
                     ])
-                    .into(),
-                    Instruction::Right.into(),
-                    // On scratch1 i.e. 1 iff count!=0 else 0
-                    Instruction::Left.into(),
-                    Instruction::Left.into(),
-                    Instruction::Left.into(),
+                }
+
+                // Unmarked entries were left behind in an earlier round, so their counts are below
+                // this one, and only marked entries can be greater
+                Item::Sequence(vec![
+                    copy(ListEntry::COUNT, ListEntry::COMPARE + Compare::A),
+                    copy(ListEntry::ROUND, ListEntry::COMPARE + Compare::B),
+                    offset_to_insns(offset_from(ListEntry::EXIST_FLAG, ListEntry::COMPARE)),
+                    Compare::compare(),
+                    // Whatever's left of the count or the round, and the other flag
+                    offset_to_insns(Compare::A as _),
                     zero_cell(),
-                    Instruction::Right.into(),
-                    Loop::new(vec![
-                        Instruction::Dec.into(),
-                        Instruction::Left.into(),
-                        Instruction::Inc.into(),
-                        Instruction::Right.into(),
-                        Instruction::Right.into(),
-                        Instruction::Right.into(),
-                        Instruction::Inc.into(),
-                        Instruction::Left.into(),
-                        Instruction::Left.into(),
-                    ])
-                    .into(),
-                    Instruction::Left.into(),
-                    Item::assert_marker_offset("current item", 0, "return to exist"),
-                    drain(&[1], true),
-                    Instruction::Inc.into(),
-                    Instruction::Right.into(),
-                    Instruction::Right.into(),
-                    Instruction::Right.into(),
-                    Instruction::Dec.into(),
-                    Instruction::Dec.into(),
-                    // If 0, mark
-                    Instruction::Left.into(),
-                    Instruction::Inc.into(),
-                    Instruction::Right.into(),
-                    Loop::new(vec![
-                        zero_cell_up(),
-                        Instruction::Left.into(),
-                        Instruction::Dec.into(),
-                        Instruction::Right.into(),
-                    ])
-                    .into(),
-                    offset_to_insns(offset_from(ListEntry::SCRATCH + 1, ListEntry::WIDTH)),
+                    offset_to_insns(offset_from(Compare::A, Compare::B)),
+                    zero_cell(),
+                    offset_to_insns(offset_from(Compare::B, Compare::LESS)),
+                    zero_cell(),
+                    offset_to_insns(offset_from(ListEntry::COMPARE + Compare::LESS, ListEntry::WIDTH)),
                 ])
             }),
             list_pass("check for any greater items", |brk| {
                 Item::Sequence(vec![
-                    Instruction::Right.into(),
-                    Instruction::Right.into(),
+                    offset_to_insns(offset_from(ListEntry::EXIST_FLAG, ListEntry::GREATER)),
                     Loop::new(vec![
                         drain(&[1], true),
-                        Instruction::Left.into(),
-                        Instruction::Left.into(),
+                        offset_to_insns(offset_from(ListEntry::GREATER, ListEntry::EXIST_FLAG)),
                         Item::assert_marker_offset("current item", ListEntry::EXIST_FLAG as _, "exist flag"),
                         Loop::new(vec![Instruction::Left.conv::<Item>().repeat(ListEntry::WIDTH)]).into(),
-                        Item::custom(move |_, _, _| brk.store(true, Ordering::SeqCst)),
+                        Item::custom(move |_, _, _| {
+                            brk.store(true, Ordering::SeqCst);
+                            Ok(())
+                        }),
                         Item::assert_position(Positions::LIST_HEADSTOP, "return to headstop"),
                         offset_to_insns(offset_from(Positions::LIST_HEADSTOP, Positions::GREATER_FLAG)),
                         zero_cell(),
@@ -1405,19 +990,17 @@ fn output() -> Item {
                         offset_to_insns(offset_from(Positions::GREATER_FLAG, Positions::LIST_START)),
                         Loop::new(vec![Instruction::Right.conv::<Item>().repeat(ListEntry::WIDTH)]).into(),
                         Instruction::Right.conv::<Item>().repeat(ListEntry::WIDTH),
-                        offset_to_insns(offset_from(ListEntry::WIDTH, 2)),
+                        offset_to_insns(offset_from(ListEntry::WIDTH, ListEntry::GREATER)),
                     ])
                     .into(),
-                    offset_to_insns(offset_from(2, ListEntry::WIDTH)),
+                    offset_to_insns(offset_from(ListEntry::GREATER, ListEntry::WIDTH)),
                 ])
             }),
             list_pass("restore greater markers", |_| {
                 Item::Sequence(vec![
-                    Instruction::Right.into(),
-                    Instruction::Right.into(),
-                    Instruction::Right.into(),
+                    offset_to_insns(offset_from(ListEntry::EXIST_FLAG, ListEntry::GREATER + 1)),
                     drain(&[-1], true),
-                    offset_to_insns(offset_from(3, ListEntry::WIDTH)),
+                    offset_to_insns(offset_from(ListEntry::GREATER + 1, ListEntry::WIDTH)),
                 ])
             }),
             offset_to_insns(offset_from(Positions::LIST_START, Positions::LIST_LOOP_FLAG)),
@@ -1432,39 +1015,31 @@ fn output() -> Item {
                 offset_to_insns(offset_from(Positions::LIST_LOOP_FLAG, Positions::GENERAL_COUNT)),
                 Instruction::Inc.into(),
                 offset_to_insns(offset_from(Positions::GENERAL_COUNT, Positions::LIST_START)),
-                list_pass("decrement", |_| {
+                list_pass("next round", |_| {
                     Item::Sequence(vec![
+                        // if greater, clear it
+                        // else, unmark, as the count is at most the maximum
                         offset_to_insns(offset_from(ListEntry::EXIST_FLAG, ListEntry::SCRATCH)),
-                        zero_cell(),
-                        offset_to_insns(offset_from(ListEntry::SCRATCH, ListEntry::COUNT)),
-                        // if zero, clear mark
-                        // else, decrement
-                        Instruction::Left.into(),
                         Instruction::Inc.into(),
-                        Instruction::Right.into(),
+                        offset_to_insns(offset_from(ListEntry::SCRATCH, ListEntry::GREATER)),
                         Loop::new(vec![
-                            drain(&[-2], true),
-                            Instruction::Left.into(),
-                            Instruction::Left.into(),
-                            Instruction::Dec.into(),
-                            Instruction::Right.into(),
+                            zero_cell(),
+                            offset_to_insns(offset_from(ListEntry::GREATER, ListEntry::SCRATCH)),
                             Instruction::Dec.into(),
-                            Instruction::Right.into(),
+                            offset_to_insns(offset_from(ListEntry::SCRATCH, ListEntry::GREATER)),
                         ])
                         .into(),
-                        Instruction::Left.into(),
-                        Instruction::Left.into(),
-                        drain(&[2], true),
-                        Instruction::Right.into(),
-                        // [if 1: above if zero]
+                        offset_to_insns(offset_from(ListEntry::GREATER, ListEntry::SCRATCH)),
                         Loop::new(vec![
                             zero_cell(),
-                            offset_to_insns(offset_from(ListEntry::COUNT - 1, ListEntry::MARKED_FLAG)),
+                            offset_to_insns(offset_from(ListEntry::SCRATCH, ListEntry::MARKED_FLAG)),
                             zero_cell(),
-                            offset_to_insns(offset_from(ListEntry::MARKED_FLAG, ListEntry::COUNT - 1)),
+                            offset_to_insns(offset_from(ListEntry::MARKED_FLAG, ListEntry::SCRATCH)),
                         ])
                         .into(),
-                        offset_to_insns(offset_from(ListEntry::COUNT - 1, ListEntry::WIDTH)),
+                        offset_to_insns(offset_from(ListEntry::SCRATCH, ListEntry::ROUND)),
+                        Instruction::Inc.into(),
+                        offset_to_insns(offset_from(ListEntry::ROUND, ListEntry::WIDTH)),
                     ])
                 }),
                 offset_to_insns(offset_from(Positions::LIST_START, Positions::GREATER_FLAG)),
@@ -1487,7 +1062,10 @@ fn output() -> Item {
                     Item::assert_marker_offset("current item", ListEntry::EXIST_FLAG as _, "exist flag"),
                     Item::add_marker("target IP"),
                     zero_cell(),
-                    Item::custom(move |_, _, _| brk.store(true, Ordering::SeqCst)),
+                    Item::custom(move |_, _, _| {
+                        brk.store(true, Ordering::SeqCst);
+                        Ok(())
+                    }),
                     pull_back(0),
                     pull_back(1),
                     pull_back(2),
@@ -1531,7 +1109,7 @@ fn output() -> Item {
         // Clear the first entry as we (may?) need the space
         Item::Sequence(vec![zero_cell(), Instruction::Right.into()]).repeat(ListEntry::WIDTH),
         offset_to_insns(offset_from(Positions::LIST_START + ListEntry::WIDTH, Positions::TEXT_SPACE)),
-        write_text(Text::MostPopular),
+        write_text("Most popular destination"),
         offset_to_insns(offset_from(Positions::TEXT_SPACE, Positions::TARGET_COUNT - 1)),
         /*
         Format:
@@ -1544,7 +1122,7 @@ fn output() -> Item {
         // If nonzero, `cell` extra destinations
         Loop::new(vec![
             offset_to_insns(offset_from(Positions::TARGET_COUNT, Positions::TEXT_SPACE)),
-            write_text(Text::DestinationsWere),
+            write_text("s were "),
             offset_to_insns(offset_from(Positions::TEXT_SPACE, Positions::TARGET_COUNT)),
             drain(&[-2], true),
             Instruction::Left.into(),
@@ -1556,7 +1134,7 @@ fn output() -> Item {
         Loop::new(vec![
             zero_cell(),
             offset_to_insns(offset_from(Positions::TARGET_COUNT - 1, Positions::TEXT_SPACE)),
-            write_text(Text::DestinationWas),
+            write_text(" was "),
             offset_to_insns(offset_from(Positions::TEXT_SPACE, Positions::TARGET_COUNT - 1)),
         ])
         .into(),
@@ -1566,7 +1144,10 @@ fn output() -> Item {
         Instruction::Dec.into(),
         Instruction::Left.conv::<Item>().repeat(9),
         // set cell to b'.'
-        Item::parse("+++++++[>+++++++<-]>---").expect("should be valid"),
+        Instruction::Inc.conv::<Item>().repeat(7),
+        drain_scaled(&[(1, 7)], true),
+        Instruction::Right.into(),
+        Instruction::Dec.conv::<Item>().repeat(3),
         drain(&[1, 1, 1, 1], true),
         offset_to_insns(5),
         Loop::new(vec![
@@ -1574,7 +1155,7 @@ fn output() -> Item {
             offset_to_insns(8),
             drain(&[6], true),
             offset_to_insns(6),
-            print_decimal_cell(),
+            write_number(1),
             zero_cell(),
             offset_to_insns(-(6 + 8)),
             // print b'.' if required
@@ -1587,25 +1168,29 @@ fn output() -> Item {
             .into(),
             Instruction::Right.into(),
         ])
+        .unbalanced()
         .indent()
         .into(),
         Item::assert_position(Positions::TARGET_COUNT - 2 - 1, "after IP output"), // -2 because it was `drain`ed to the left
         offset_to_insns(-5),
-        Loop::new(vec![zero_cell(), Instruction::Left.into()]).into(),
+        Loop::new(vec![zero_cell(), Instruction::Left.into()]).unbalanced().into(),
         Item::assert_position(Positions::TARGET_COUNT - 2 - 10, "after IP cleanup"),
         offset_to_insns(offset_from(Positions::TARGET_COUNT - 2 - 10, Positions::TARGET_COUNT - 2)),
         // If nonzero, `cell` extra destinations
         Instruction::Left.into(),
         Instruction::Left.into(),
-        Item::parse("+++++++++++[>++++++++++<-]>+++++").expect("should be valid"),
+        Instruction::Inc.conv::<Item>().repeat(11),
+        drain_scaled(&[(1, 10)], true),
+        Instruction::Right.into(),
+        Instruction::Inc.conv::<Item>().repeat(5),
         Instruction::Right.into(),
         Loop::new(vec![
             offset_to_insns(offset_from(Positions::TARGET_COUNT - 2, Positions::TEXT_SPACE)),
-            write_text(Text::And),
+            write_text(" and "),
             offset_to_insns(offset_from(Positions::TEXT_SPACE, Positions::TARGET_COUNT - 2)),
-            print_decimal_cell(),
+            write_number(1),
             offset_to_insns(offset_from(Positions::TARGET_COUNT - 2, Positions::TEXT_SPACE)),
-            write_text(Text::Other),
+            write_text(" other"),
             // Leave a marker of multiple IPs for later
             Instruction::Left.into(),
             Instruction::Inc.into(),
@@ -1622,11 +1207,11 @@ fn output() -> Item {
         ])
         .into(),
         offset_to_insns(offset_from(Positions::TARGET_COUNT - 2, Positions::TEXT_SPACE)),
-        write_text(Text::With),
+        write_text(" with "),
         offset_to_insns(offset_from(Positions::TEXT_SPACE, Positions::GENERAL_COUNT)),
-        print_decimal_cell(),
+        write_number(1),
         offset_to_insns(offset_from(Positions::GENERAL_COUNT, Positions::TEXT_SPACE)),
-        write_text(Text::Packet),
+        write_text(" packet"),
         offset_to_insns(offset_from(Positions::TEXT_SPACE, Positions::GENERAL_COUNT)),
         Instruction::Dec.into(),
         Loop::new(vec![
@@ -1637,12 +1222,31 @@ fn output() -> Item {
         ])
         .into(),
         offset_to_insns(offset_from(Positions::GENERAL_COUNT, Positions::TEXT_SPACE - 1)),
-        Loop::new(vec![zero_cell(), write_text(Text::Each)]).into(),
-        write_text(Text::Newline),
+        Loop::new(vec![zero_cell(), write_text(" each")]).into(),
+        write_text("\n"),
     ])
 }
 
 fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args_os().skip(1);
+    let mut limit_bytes = None;
+    let mut packets = None;
+    let mut profile = false;
+    while let Some(arg) = args.next() {
+        if arg == "--file" {
+            let path = args.next().ok_or_else(|| anyhow!("`--file` requires a path"))?;
+            return run_file(path.as_ref());
+        } else if arg == "--limit-bytes" {
+            limit_bytes = Some(parse_count(args.next(), "--limit-bytes")?);
+        } else if arg == "--packets" {
+            packets = Some(parse_count(args.next(), "--packets")?);
+        } else if arg == "--profile" {
+            profile = true;
+        } else {
+            bail!("unexpected argument {arg:?}, expected `--file PATH`, `--limit-bytes N`, `--packets N` or `--profile`");
+        }
+    }
+
     let program = vec![
         discard_header(),
         setup_state(),
@@ -1651,21 +1255,49 @@ fn main() -> anyhow::Result<()> {
         output(),
     ];
 
-    let program = Program::build(program.clone().build())?;
+    let program = Program::build(Item::Sequence(program))?;
     // println!("{}", program.as_text());
     fs_err::write("program.bf", collapse(program.as_text_clean()))?;
-    let data = fs_err::read("packet-storm.pcap")?;
+    let mut data = fs_err::read("packet-storm.pcap")?;
+    // For debugging, only run the program on the start of the capture
+    if let Some(packets) = packets {
+        data = packet_storm::Capture::new(&data)?.prefix(packets).to_vec();
+    }
+    if let Some(limit_bytes) = limit_bytes {
+        data.truncate(limit_bytes);
+    }
     let input = Cursor::new(data);
 
     let mut interpreter = Interpreter::new(program, input);
-    interpreter.set_print_level(160);
+    interpreter.set_print_level(CommentLevel::INFO);
+    interpreter.set_profiling(profile);
     interpreter.run()?;
+    for (section, count) in interpreter.section_profile() {
+        eprintln!("{count:>12} {section}");
+    }
     // println!("\n\n===\n");
     // println!("{}", interpreter.tape());
 
     Ok(())
 }
 
+fn parse_count(arg: Option<OsString>, flag: &str) -> anyhow::Result<usize> {
+    let arg = arg.ok_or_else(|| anyhow!("`{flag}` requires a count"))?;
+    arg.to_str()
+        .and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| anyhow!("`{flag}` requires a count, found {arg:?}"))
+}
+
+// Runs an arbitrary brainfuck file with stdin as its input
+fn run_file(path: &Path) -> anyhow::Result<()> {
+    let source = fs_err::read_to_string(path)?;
+    let program = Program::build(Item::parse_lenient(&source))
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+
+    let mut interpreter = Interpreter::new(program, std::io::stdin());
+    interpreter.run()
+}
+
 fn collapse(mut bf: String) -> String {
     loop {
         let mut new = bf.clone();
@@ -1,86 +1,419 @@
 use std::{
-    fmt::{Debug, Formatter},
-    net::Ipv4Addr,
+    fmt::{Debug, Display, Formatter},
+    io::Read,
+    net::{IpAddr, Ipv4Addr},
+    time::{Duration, SystemTime},
 };
 
 use anyhow::{anyhow, bail};
 
+mod pcapng;
+mod reassembly;
+mod summary;
+mod writer;
+
+pub use pcapng::{NgRecords, PcapNg};
+pub use reassembly::Reassembler;
+pub use summary::Summary;
+pub use writer::CaptureWriter;
+
 pub struct Capture<'a> {
     data: &'a [u8],
+    version: (u16, u16),
+    snap_len: u32,
+    link_type: LinkType,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum LinkType {
+    Ethernet,
+    /// The records start directly with the IP header
+    RawIp,
+}
+
+impl LinkType {
+    fn as_u32(self) -> u32 {
+        match self {
+            Self::Ethernet => 1,
+            Self::RawIp => 101,
+        }
+    }
 }
 
 impl<'a> Capture<'a> {
-    const HEADER_LENGTH: usize = 24;
+    /// The length of the header at the start of the capture, before the first record.
+    pub const HEADER_LENGTH: usize = 24;
 
-    pub fn new(data: &'a [u8]) -> anyhow::Result<Self> {
+    pub fn new(data: &'a [u8]) -> Result<Self, CaptureError> {
+        if data.len() < Self::HEADER_LENGTH {
+            return Err(CaptureError::TruncatedHeader);
+        }
         let mut position = 0;
         let magic = u32::read_le(data, &mut position);
-        assert_eq!(magic, 0xA1B2C3D4);
-        let ver = (u16::read_le(data, &mut position), u16::read_le(data, &mut position));
-        assert_eq!(ver, (2, 4));
+        if magic != 0xA1B2C3D4 {
+            return Err(CaptureError::BadMagic(magic));
+        }
+        let version = (u16::read_le(data, &mut position), u16::read_le(data, &mut position));
+        if version != (2, 4) {
+            return Err(CaptureError::UnsupportedVersion(version.0, version.1));
+        }
         position += u32::BYTES; // Reserved 1
         position += u32::BYTES; // Reserved 2
         let snap_len = u32::read_le(data, &mut position);
-        assert_eq!(snap_len, u16::MAX as u32);
-        let link_type = u32::read_le(data, &mut position);
-        assert_eq!(link_type, 1); // Ethernet
+        if snap_len == 0 {
+            return Err(CaptureError::ZeroSnapLen);
+        }
+        let link_type = match u32::read_le(data, &mut position) {
+            1 => LinkType::Ethernet,
+            101 => LinkType::RawIp,
+            other => return Err(CaptureError::UnsupportedLinkType(other)),
+        };
 
         assert_eq!(position, Self::HEADER_LENGTH);
-        Ok(Self { data })
+        Ok(Self {
+            data,
+            version,
+            snap_len,
+            link_type,
+        })
+    }
+
+    /// The (major, minor) version of the pcap format.
+    pub fn version(&self) -> (u16, u16) {
+        self.version
+    }
+
+    /// The most bytes captured of any one frame, commonly 65535 or 262144 but sometimes much smaller
+    /// (e.g. 96 for headers only). Longer frames are cut short, see [`PhysicalFrame::was_truncated`].
+    pub fn snap_len(&self) -> u32 {
+        self.snap_len
+    }
+
+    /// The `LINKTYPE_` value from the header, e.g. `1` for Ethernet.
+    pub fn link_type(&self) -> u32 {
+        self.link_type.as_u32()
+    }
+
+    /// Decompresses `data` if it's gzipped (e.g. a `.pcap.gz`), otherwise returns it unchanged.
+    ///
+    /// As a `Capture` only borrows its data, this is separate from [`Capture::new`].
+    pub fn decompress(data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        if !data.starts_with(&[0x1F, 0x8B]) {
+            return Ok(data);
+        }
+        let mut decompressed = vec![];
+        flate2::read::MultiGzDecoder::new(&*data).read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    }
+
+    /// The timestamps of the first and last records, which is an error for an empty capture or
+    /// one with a malformed record.
+    pub fn time_range(&self) -> anyhow::Result<(SystemTime, SystemTime)> {
+        let mut records = self.records();
+        let first = records.next().map(|first| first.timestamp());
+        let last = records.by_ref().last().map(|last| last.timestamp());
+        if let Some(error) = records.error() {
+            bail!(error);
+        }
+        let first = first.ok_or_else(|| anyhow!("capture has no records"))?;
+        Ok((first, last.unwrap_or(first)))
+    }
+
+    /// Iterates over the records, stopping early at a malformed one (see [`Records::error`]).
+    pub fn records(&self) -> Records<'a> {
+        Records::new(self.data, Self::HEADER_LENGTH, self.link_type)
+    }
+
+    /// As [`Capture::records`], but over `data` that carries on from this capture's records rather
+    /// than from the start of the capture, e.g. the records appended to a file since the capture
+    /// was first read. `data` must start at the start of a record.
+    pub fn records_in<'b>(&self, data: &'b [u8]) -> Records<'b> {
+        Records::new(data, 0, self.link_type)
+    }
+
+    /// Counts the records by only reading each record header's captured length, without looking
+    /// at the frames themselves.
+    pub fn record_count(&self) -> anyhow::Result<usize> {
+        let mut position = Self::HEADER_LENGTH;
+        let mut count = 0;
+        while position < self.data.len() {
+            position = self
+                .record_end(position)
+                .ok_or_else(|| anyhow!("capture truncated in record {count}"))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Sums the captured length of every record, from the record headers alone. This counts whole
+    /// frames (link-layer headers included) as stored in the file, not the IP-level data.
+    pub fn total_captured_bytes(&self) -> anyhow::Result<u64> {
+        let mut position = Self::HEADER_LENGTH;
+        let mut total = 0;
+        let mut index = 0;
+        while position < self.data.len() {
+            let end = self
+                .record_end(position)
+                .ok_or_else(|| anyhow!("capture truncated in record {index}"))?;
+            total += (end - position - PhysicalFrame::HEADER_LENGTH) as u64;
+            position = end;
+            index += 1;
+        }
+        Ok(total)
+    }
+
+    /// Iterates over the records starting from the `record_index`th, found by walking the record
+    /// headers. An index equal to the number of records gives an empty iterator.
+    pub fn records_from(&self, record_index: usize) -> anyhow::Result<Records<'a>> {
+        let mut records = self.records();
+        for index in 0..record_index {
+            if records.position == self.data.len() {
+                bail!("record index {record_index} is out of bounds, the capture has {index} records");
+            }
+            records.position = self
+                .record_end(records.position)
+                .ok_or_else(|| anyhow!("capture truncated in record {index}"))?;
+        }
+        Ok(records)
+    }
+
+    /// The capture cut down to its header and first `records` records (or as many as it has), e.g.
+    /// to run something slow over just the start of a large capture.
+    pub fn prefix(&self, records: usize) -> &'a [u8] {
+        let mut walked = self.records();
+        walked.by_ref().take(records).for_each(drop);
+        &self.data[..walked.position()]
+    }
+
+    /// Yields every `every`th record, starting with the first. The records in between are jumped
+    /// over using only their headers, so this is faster than `records().step_by(every)` for
+    /// sampling a large capture.
+    ///
+    /// Panics if `every` is 0.
+    pub fn sample(&self, every: usize) -> impl Iterator<Item = PhysicalFrame<'_>> {
+        assert!(every > 0, "sample interval must be nonzero");
+        let mut records = self.records();
+        std::iter::from_fn(move || {
+            let frame = records.next()?;
+            for _ in 1..every {
+                // A partial record is left for `next` to treat as the end of the capture
+                let Some(end) = self.record_end(records.position) else {
+                    break;
+                };
+                records.position = end;
+            }
+            Some(frame)
+        })
     }
 
-    pub fn records(&self) -> Records<'_> {
-        Records::new(self)
+    /// Whether any IPv4 packet in the capture is from or to `addr`, stopping at the first match.
+    ///
+    /// Only the addresses are read from each packet, and frames that aren't IPv4 are skipped, so
+    /// this is cheaper than going through [`FrameIter::filter_ip`].
+    pub fn contains_ip(&self, addr: Ipv4Addr) -> anyhow::Result<bool> {
+        let mut position = Self::HEADER_LENGTH;
+        let mut index = 0;
+        while position < self.data.len() {
+            if self.record_end(position).is_none() {
+                bail!("capture truncated in record {index}");
+            }
+            let frame = PhysicalFrame::read(self.data, &mut position, self.link_type)?;
+            if frame.ipv4_addresses().is_some_and(|(source, dest)| source == addr || dest == addr) {
+                return Ok(true);
+            }
+            index += 1;
+        }
+        Ok(false)
+    }
+
+    fn record_end(&self, position: usize) -> Option<usize> {
+        record_end(self.data, position)
+    }
+}
+
+// The position after the record starting at `position`, or `None` if there isn't a whole record
+// left
+fn record_end(data: &[u8], position: usize) -> Option<usize> {
+    if position + PhysicalFrame::HEADER_LENGTH > data.len() {
+        return None;
     }
+    let mut captured_position = position + 2 * u32::BYTES;
+    let captured = u32::read_le(data, &mut captured_position) as usize;
+    let end = position + PhysicalFrame::HEADER_LENGTH + captured;
+    (end <= data.len()).then_some(end)
 }
 
 pub struct Records<'a> {
-    pcap: &'a Capture<'a>,
+    data: &'a [u8],
     position: usize,
+    link_type: LinkType,
+    error: Option<CaptureError>,
 }
 
 impl<'a> Records<'a> {
-    fn new(pcap: &'a Capture<'a>) -> Self {
+    fn new(data: &'a [u8], position: usize, link_type: LinkType) -> Self {
         Self {
-            pcap,
-            position: Capture::HEADER_LENGTH,
+            data,
+            position,
+            link_type,
+            error: None,
         }
     }
+
+    /// Where the next record starts in the data being read, i.e. just after the last one yielded,
+    /// e.g. to cut a capture down to its first few records. This counts from the start of the
+    /// capture, or of the data given to [`Capture::records_in`].
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The malformed record that ended the iteration early, if any. A partial record at the end
+    /// of the capture isn't an error, and just ends the iteration.
+    pub fn error(&self) -> Option<CaptureError> {
+        self.error
+    }
+}
+
+/// Ways to pick out packets from the frames of a capture, for [`Records`], [`NgRecords`] and any
+/// other iterator over frames (e.g. [`Capture::sample`]).
+pub trait FrameIter<'a>: Iterator<Item = PhysicalFrame<'a>> + Sized {
+    /// Yields only the IP packets matching `pred`, quietly skipping any records that are not
+    /// valid IP packets.
+    fn filter_ip(self, pred: impl Fn(&IpPacket<'a>) -> bool) -> impl Iterator<Item = IpPacket<'a>> {
+        self.filter_map(|frame| frame.ip().ok()).filter(move |packet| pred(packet))
+    }
+
+    /// Pairs each frame with its timestamp, as time since the Unix epoch.
+    fn timestamped(self) -> impl Iterator<Item = (Duration, PhysicalFrame<'a>)> {
+        self.map(|frame| (frame.timestamp, frame))
+    }
+
+    /// The source, destination and [payload](IpPacket::transport_payload) of each `protocol`
+    /// packet, skipping everything else as [`FrameIter::filter_ip`] does. A matching packet with
+    /// malformed transport headers is yielded as an error.
+    fn transport_payloads(self, protocol: Protocol) -> impl Iterator<Item = anyhow::Result<(IpAddr, IpAddr, &'a [u8])>> {
+        self.filter_ip(move |packet| packet.protocol == protocol)
+            .map(|packet| Ok((packet.source, packet.dest, packet.transport_payload()?)))
+    }
 }
 
+impl<'a, I: Iterator<Item = PhysicalFrame<'a>>> FrameIter<'a> for I {}
+
 impl<'a> Iterator for Records<'a> {
     type Item = PhysicalFrame<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.position == self.pcap.data.len() {
+        if self.error.is_some() {
             return None;
         }
-        let frame = PhysicalFrame::read(self.pcap.data, &mut self.position).unwrap();
-        Some(frame)
+        // Trailing bytes too short to be a whole record (e.g. from an interrupted capture) are
+        // treated as the end of the capture
+        let end = record_end(self.data, self.position)?;
+        let mut position = self.position;
+        match PhysicalFrame::read(self.data, &mut position, self.link_type) {
+            Ok(frame) => {
+                debug_assert_eq!(position, end);
+                self.position = position;
+                Some(frame)
+            }
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
     }
 }
 
 pub struct PhysicalFrame<'a> {
     data: &'a [u8],
+    link_type: LinkType,
+    // Since the Unix epoch
+    timestamp: Duration,
+    truncated: bool,
 }
 
 impl<'a> PhysicalFrame<'a> {
-    fn read(data: &'a [u8], position: &mut usize) -> anyhow::Result<Self> {
-        *position += u32::BYTES; // TS - seconds
-        *position += u32::BYTES; // TS - micro/nanos
+    const HEADER_LENGTH: usize = 4 * u32::BYTES;
+
+    fn read(data: &'a [u8], position: &mut usize, link_type: LinkType) -> Result<Self, CaptureError> {
+        let seconds = u32::read_le(data, position);
+        let micros = u32::read_le(data, position); // The magic number means micro, not nano, seconds
         let captured = u32::read_le(data, position);
         let original = u32::read_le(data, position);
-        if captured != original {
-            bail!("packet was truncated")
+        if captured > original {
+            return Err(CaptureError::BadRecordLength(captured, original));
         }
-        let enclosed_data = &data[*position..*position + captured as usize];
+        let enclosed_data = data
+            .get(*position..*position + captured as usize)
+            .ok_or(CaptureError::Truncated)?;
         *position += captured as usize;
-        Ok(Self { data: enclosed_data })
+        Ok(Self {
+            data: enclosed_data,
+            link_type,
+            // Malformed captures can have a million or more microseconds, which just carry over
+            timestamp: Duration::from_secs(seconds.into()) + Duration::from_micros(micros.into()),
+            truncated: captured < original,
+        })
+    }
+
+    /// Whether only the start of the frame was captured, as it was longer than the capture's
+    /// snap length.
+    pub fn was_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// When the frame was captured.
+    pub fn timestamp(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + self.timestamp
+    }
+
+    /// The captured bytes of the whole frame, link-layer header included. These borrow from the
+    /// capture rather than the frame, so they can be kept after parsing it with [`PhysicalFrame::ip`].
+    pub fn raw(&self) -> &'a [u8] {
+        self.data
     }
 
-    pub fn ip(self) -> anyhow::Result<IpPacket<'a>> {
+    /// The EtherType from the Ethernet header, e.g. `0x0800` for IPv4, `0x0806` for ARP or `0x86DD`
+    /// for IPv6, to handle frames that [`PhysicalFrame::ip`] doesn't. Frames from a raw IP capture
+    /// have no Ethernet header, so it's inferred from the IP version instead.
+    pub fn ethertype(&self) -> anyhow::Result<u16> {
+        match self.link_type {
+            LinkType::Ethernet => {
+                if self.data.len() < 14 {
+                    bail!("frame is too short for an Ethernet header ({} bytes)", self.data.len());
+                }
+                let mut position = 12;
+                Ok(u16::read_be(self.data, &mut position))
+            }
+            LinkType::RawIp => match self.data.first().map(|byte| byte >> 4) {
+                Some(4) => Ok(0x0800),
+                Some(6) => Ok(0x86DD),
+                Some(version) => bail!("expected an IPv4 or IPv6 packet, found IPv{version}"),
+                None => bail!("frame is empty"),
+            },
+        }
+    }
+
+    pub fn ip(self) -> Result<IpPacket<'a>, CaptureError> {
         IpPacket::new(self)
     }
+
+    // The source and destination of an IPv4 packet, without checking the rest of its header
+    fn ipv4_addresses(&self) -> Option<(Ipv4Addr, Ipv4Addr)> {
+        let ip = match self.link_type {
+            LinkType::Ethernet if self.data.get(12..14)? == [0x08, 0x00] => &self.data[14..],
+            LinkType::Ethernet => return None,
+            LinkType::RawIp => self.data,
+        };
+        if ip.first()? >> 4 != 4 {
+            return None;
+        }
+        let mut position = 12; // Up to the source address
+        let addresses = ip.get(..position + 8)?;
+        let source = <[u8; 4]>::read_be(addresses, &mut position).into();
+        let dest = <[u8; 4]>::read_be(addresses, &mut position).into();
+        Some((source, dest))
+    }
 }
 
 impl Debug for PhysicalFrame<'_> {
@@ -99,56 +432,193 @@ impl Debug for PhysicalFrame<'_> {
 pub struct IpPacket<'a> {
     pub data: &'a [u8],
     pub protocol: Protocol,
-    pub source: Ipv4Addr,
-    pub dest: Ipv4Addr,
+    pub source: IpAddr,
+    pub dest: IpAddr,
+    /// The payload length given by the IP header, which is more than `data.len()` if the capture
+    /// cut the packet short.
+    pub declared_length: usize,
+    /// Shared by all the fragments of a packet, so they can be matched up
+    pub identification: u16,
+    /// Whether more fragments follow this one, i.e. the More Fragments flag
+    pub more_fragments: bool,
+    /// Where this fragment's data goes in the whole packet's, in bytes
+    pub fragment_offset: usize,
 }
 
 impl<'a> IpPacket<'a> {
-    fn new(phys: PhysicalFrame<'a>) -> anyhow::Result<Self> {
-        // Ethernet Frame
+    fn new(phys: PhysicalFrame<'a>) -> Result<Self, CaptureError> {
         let data = phys.data;
         let mut position = 0;
-        position += 6; // Destination MAC
-        position += 6; // Source MAC
-        let type_length = u16::read_be(data, &mut position) as usize;
-        if type_length != 0x800 {
-            bail!("expected an IP(v4) record, found 0x{type_length:04X}");
+        if let LinkType::Ethernet = phys.link_type {
+            // Ethernet Frame
+            if data.len() < 14 {
+                return Err(CaptureError::FrameTooShort(data.len()));
+            }
+            position += <[u8; 6]>::BYTES; // Destination MAC
+            position += <[u8; 6]>::BYTES; // Source MAC
+            let type_length = u16::read_be(data, &mut position);
+            if type_length != 0x800 {
+                return Err(CaptureError::UnsupportedEtherType(type_length));
+            }
         }
 
         // IPv4 Frame
+        // Even a frame cut short by the snap length must have the whole IP header
+        if data.len() < position + 20 {
+            return Err(CaptureError::FrameTooShort(data.len()));
+        }
         let ip_start = position;
         let magic = u8::read_be(data, &mut position);
         let version = (magic & 0xF0) >> 4;
         let ihl = magic & 0x0F;
         if version != 4 {
-            bail!("expected an IPv4 record")
+            return Err(CaptureError::UnsupportedIpVersion(version));
         }
         if ihl != 5 {
-            bail!("IPv4 header had options specified")
+            return Err(CaptureError::IpOptions);
         }
         position += 1; // DSCP + ECN
         let total_length = u16::read_be(data, &mut position);
-        position += 2; // Identification
-        position += 2; // Flags + Fragment offset
+        let identification = u16::read_be(data, &mut position);
+        let flags_and_offset = u16::read_be(data, &mut position);
         position += 1; // TTL
         let protocol = u8::read_be(data, &mut position);
         position += 2; // Header checksum - we just assume this is valid
 
-        let source = Ipv4Addr::from(u32::read_be(data, &mut position));
-        let dest = Ipv4Addr::from(u32::read_be(data, &mut position));
+        let source = Ipv4Addr::from(<[u8; 4]>::read_be(data, &mut position)).into();
+        let dest = Ipv4Addr::from(<[u8; 4]>::read_be(data, &mut position)).into();
 
         let protocol = Protocol::from_byte(protocol)?;
-        let data_length = (total_length as usize) - (position - ip_start);
         debug_assert_eq!(position - ip_start, 20); // As ihl is 5
-        let ip_data = &data[position..];
-        debug_assert_eq!(data_length, ip_data.len());
+        let data_length = (total_length as usize)
+            .checked_sub(position - ip_start)
+            .ok_or(CaptureError::InvalidTotalLength(total_length))?;
+        // There may be trailing bytes after the IP packet, e.g. as short frames are padded out to
+        // Ethernet's minimum size, or a frame check sequence or vendor trailer was captured
+        let ip_data = match data.get(position..position + data_length) {
+            Some(ip_data) => ip_data,
+            // All that was captured of the packet
+            None if phys.truncated => &data[position..],
+            None => return Err(CaptureError::InvalidTotalLength(total_length)),
+        };
         Ok(Self {
             data: ip_data,
             protocol,
             source,
             dest,
+            declared_length: data_length,
+            identification,
+            more_fragments: flags_and_offset & 0x2000 != 0,
+            // In units of 8 bytes
+            fragment_offset: (flags_and_offset & 0x1FFF) as usize * 8,
         })
     }
+
+    /// Whether this is only part of a fragmented packet, which can be put back together with a
+    /// [`Reassembler`]. Only the first fragment has the transport header.
+    pub fn is_fragment(&self) -> bool {
+        self.more_fragments || self.fragment_offset != 0
+    }
+
+    /// Copies the packet's data, so it no longer borrows the capture.
+    pub fn to_owned(&self) -> OwnedIpPacket {
+        OwnedIpPacket {
+            data: self.data.to_vec(),
+            protocol: self.protocol,
+            source: self.source,
+            dest: self.dest,
+            declared_length: self.declared_length,
+            identification: self.identification,
+            more_fragments: self.more_fragments,
+            fragment_offset: self.fragment_offset,
+        }
+    }
+
+    /// Whether the capture is missing some of the payload, i.e. `data` is shorter than the length
+    /// declared in the IP header.
+    pub fn was_truncated(&self) -> bool {
+        self.data.len() < self.declared_length
+    }
+
+    /// The length declared in the UDP header, which includes the header itself.
+    pub fn udp_length(&self) -> anyhow::Result<u16> {
+        if !matches!(self.protocol, Protocol::UDP) {
+            bail!("expected a UDP packet, found {:?}", self.protocol);
+        }
+        if self.data.len() < 8 {
+            bail!("UDP packet is too short for its header ({} bytes)", self.data.len());
+        }
+        let mut position = 4;
+        Ok(u16::read_be(self.data, &mut position))
+    }
+
+    /// Checks that the declared UDP length matches the IP payload length, a mismatch usually
+    /// indicating malformed traffic. Packets cut short by the capture are compared against the
+    /// length in the IP header, so still pass.
+    pub fn validate_udp_length(&self) -> anyhow::Result<()> {
+        let declared = self.udp_length()?;
+        if declared as usize != self.declared_length {
+            bail!("UDP length mismatch: declared {declared} bytes, IP payload is {}", self.declared_length);
+        }
+        Ok(())
+    }
+
+    /// The application payload of a TCP packet, i.e. `data` without the TCP header and options.
+    pub fn tcp_payload(&self) -> anyhow::Result<&'a [u8]> {
+        if !matches!(self.protocol, Protocol::TCP) {
+            bail!("expected a TCP packet, found {:?}", self.protocol);
+        }
+        if self.data.len() < 20 {
+            bail!("TCP packet is too short for its header ({} bytes)", self.data.len());
+        }
+        let mut position = 12;
+        let data_offset = (u8::read_be(self.data, &mut position) & 0xF0) >> 4;
+        let header_length = data_offset as usize * 4;
+        if header_length < 20 {
+            bail!("TCP data offset of {data_offset} is too small");
+        }
+        if header_length > self.data.len() {
+            bail!("TCP header length of {header_length} exceeds the packet's {} bytes", self.data.len());
+        }
+        Ok(&self.data[header_length..])
+    }
+
+    /// The data after the UDP header, up to the length declared in it (or as much as was captured,
+    /// for a truncated packet).
+    pub fn udp_payload(&self) -> anyhow::Result<&'a [u8]> {
+        let length = self.udp_length()? as usize;
+        if length < 8 {
+            bail!("UDP length of {length} is too small for its header");
+        }
+        match self.data.get(8..length) {
+            Some(payload) => Ok(payload),
+            None if self.was_truncated() => Ok(&self.data[8..]),
+            None => bail!("UDP length of {length} exceeds the packet's {} bytes", self.data.len()),
+        }
+    }
+
+    /// The application-level data, i.e. [`IpPacket::tcp_payload`] or [`IpPacket::udp_payload`].
+    pub fn transport_payload(&self) -> anyhow::Result<&'a [u8]> {
+        match self.protocol {
+            Protocol::TCP => self.tcp_payload(),
+            Protocol::UDP => self.udp_payload(),
+        }
+    }
+}
+
+/// An [`IpPacket`] with its own copy of the data, e.g. to keep it after the capture is dropped
+/// or (with the `serde` feature) to serialize it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OwnedIpPacket {
+    pub data: Vec<u8>,
+    pub protocol: Protocol,
+    pub source: IpAddr,
+    pub dest: IpAddr,
+    pub declared_length: usize,
+    pub identification: u16,
+    pub more_fragments: bool,
+    pub fragment_offset: usize,
 }
 
 impl Debug for IpPacket<'_> {
@@ -159,7 +629,8 @@ impl Debug for IpPacket<'_> {
             .field("protocol", &self.protocol)
             .field("source", &self.source)
             .field("dest", &self.dest)
-            .field("length", &self.data.len());
+            .field("length", &self.data.len())
+            .field("declared_length", &self.declared_length);
         if alt {
             s.field("data", &self.data).finish()
         } else {
@@ -168,7 +639,8 @@ impl Debug for IpPacket<'_> {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(clippy::upper_case_acronyms)]
 pub enum Protocol {
     TCP,
@@ -176,15 +648,96 @@ pub enum Protocol {
 }
 
 impl Protocol {
-    fn from_byte(b: u8) -> anyhow::Result<Self> {
+    fn from_byte(b: u8) -> Result<Self, CaptureError> {
         match b {
             0x06 => Ok(Self::TCP),
             0x11 => Ok(Self::UDP),
-            _ => Err(anyhow!("unknown protocol 0x{b:02X}")),
+            _ => Err(CaptureError::UnknownProtocol(b)),
+        }
+    }
+
+    /// The IP protocol number, the inverse of `from_byte`.
+    pub fn as_u8(self) -> u8 {
+        match self {
+            Self::TCP => 0x06,
+            Self::UDP => 0x11,
         }
     }
 }
 
+/// The lowercase name, e.g. `tcp`, for reports rather than relying on `Debug`.
+impl Display for Protocol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Self::TCP => "tcp",
+            Self::UDP => "udp",
+        })
+    }
+}
+
+/// The ways a capture, or a record within it, can fail to parse.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CaptureError {
+    BadMagic(u32),
+    UnsupportedVersion(u16, u16),
+    UnsupportedLinkType(u32),
+    /// The capture ended partway through its header
+    TruncatedHeader,
+    /// The header's snap length is 0, so no frame could have been captured
+    ZeroSnapLen,
+    /// A record extends past the end of the capture
+    Truncated,
+    /// A record's captured length is longer than the packet's original length
+    BadRecordLength(u32, u32),
+    /// A frame is too short for its Ethernet or IPv4 header, e.g. as the snap length cut it off
+    FrameTooShort(usize),
+    UnsupportedEtherType(u16),
+    UnsupportedIpVersion(u8),
+    /// The IPv4 header has options, which aren't supported
+    IpOptions,
+    /// The IPv4 total length is shorter than its header or longer than the frame
+    InvalidTotalLength(u16),
+    UnknownProtocol(u8),
+    /// A pcapng block type that isn't supported
+    UnsupportedBlockType(u32),
+    /// A pcapng block's length is too short for its contents, or isn't a multiple of 4
+    BadBlockLength(u32),
+    /// A pcapng packet refers to an interface that hasn't been described
+    UnknownInterface(u32),
+    /// A pcapng `if_tsresol` too fine to represent
+    UnsupportedTimestampResolution(u8),
+}
+
+impl Display for CaptureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadMagic(magic) => write!(f, "bad magic number 0x{magic:08X}"),
+            Self::UnsupportedVersion(major, minor) => write!(f, "unsupported pcap version {major}.{minor}"),
+            Self::UnsupportedLinkType(link_type) => write!(f, "unsupported link type {link_type}"),
+            Self::TruncatedHeader => write!(f, "capture is too short for its header"),
+            Self::ZeroSnapLen => write!(f, "capture has a snap length of 0"),
+            Self::Truncated => write!(f, "record extends past the end of the capture"),
+            Self::BadRecordLength(captured, original) => {
+                write!(f, "record captured {captured} bytes of a packet of only {original} bytes")
+            }
+            Self::FrameTooShort(length) => write!(f, "frame is too short for its headers ({length} bytes)"),
+            Self::UnsupportedEtherType(ether_type) => write!(f, "expected an IP(v4) record, found 0x{ether_type:04X}"),
+            Self::UnsupportedIpVersion(version) => write!(f, "expected an IPv4 record, found IPv{version}"),
+            Self::IpOptions => write!(f, "IPv4 header had options specified"),
+            Self::InvalidTotalLength(length) => write!(f, "invalid IPv4 total length of {length}"),
+            Self::UnknownProtocol(protocol) => write!(f, "unknown protocol 0x{protocol:02X}"),
+            Self::UnsupportedBlockType(block_type) => write!(f, "unsupported pcapng block type 0x{block_type:08X}"),
+            Self::BadBlockLength(length) => write!(f, "invalid pcapng block length of {length}"),
+            Self::UnknownInterface(id) => write!(f, "pcapng packet refers to unknown interface {id}"),
+            Self::UnsupportedTimestampResolution(resolution) => {
+                write!(f, "unsupported pcapng timestamp resolution 0x{resolution:02X}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
 trait Readable: Sized {
     const BYTES: usize;
 
@@ -222,3 +775,214 @@ impl_readable! {
     u32: 4,
     u64: 8,
 }
+
+// Raw bytes (MACs, addresses) have no endianness, so both reads are the same
+impl<const N: usize> Readable for [u8; N] {
+    const BYTES: usize = N;
+
+    fn read_le(data: &[u8], position: &mut usize) -> Self {
+        let part = &data[*position..*position + N];
+        *position += N;
+        part.try_into().unwrap()
+    }
+
+    fn read_be(data: &[u8], position: &mut usize) -> Self {
+        Self::read_le(data, position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAW_IP: u32 = 101;
+
+    // A raw IP capture of `frames`, all with the same timestamp
+    fn capture(frames: &[&[u8]]) -> Vec<u8> {
+        let mut writer = CaptureWriter::new(vec![], RAW_IP).unwrap();
+        for frame in frames {
+            writer.write_frame(SystemTime::UNIX_EPOCH, frame).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    // An IPv4 packet from 10.0.0.100 to 10.0.0.`dest`
+    fn ipv4(protocol: u8, dest: u8, transport: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0];
+        packet.extend((20 + transport.len() as u16).to_be_bytes());
+        packet.extend([0, 0, 0, 0, 64, protocol, 0, 0, 10, 0, 0, 100, 10, 0, 0, dest]);
+        packet.extend(transport);
+        packet
+    }
+
+    #[test]
+    fn frame_iter() {
+        let udp = ipv4(17, 1, &[0, 53, 0, 53, 0, 11, 0, 0, b'a', b'b', b'c']);
+        let tcp = ipv4(6, 2, &[0, 1, 0, 80, 0, 0, 0, 0, 0, 0, 0, 0, 5 << 4, 0, 0, 0, 0, 0, 0, 0]);
+        let data = capture(&[&udp, &[0x60; 40], &tcp, &udp]);
+        let pcap = Capture::new(&data).unwrap();
+        let dest = |n| IpAddr::V4(Ipv4Addr::new(10, 0, 0, n));
+
+        let dests = pcap.records().filter_ip(|_| true).map(|packet| packet.dest).collect::<Vec<_>>();
+        assert_eq!(dests, [dest(1), dest(2), dest(1)]);
+        // Any iterator over frames, not just `Records`
+        let dests = pcap.sample(2).filter_ip(|_| true).map(|packet| packet.dest).collect::<Vec<_>>();
+        assert_eq!(dests, [dest(1), dest(2)]);
+        assert_eq!(pcap.sample(3).timestamped().count(), 2);
+
+        let payloads = pcap.records().transport_payloads(Protocol::UDP).collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(payloads, [(dest(100), dest(1), &b"abc"[..]); 2]);
+    }
+
+    #[test]
+    fn protocol_display() {
+        assert_eq!(Protocol::TCP.to_string(), "tcp");
+        assert_eq!(Protocol::UDP.to_string(), "udp");
+        // Width and alignment apply, as for any other string
+        assert_eq!(format!("{:>5}|{:<5}|", Protocol::TCP, Protocol::UDP), "  tcp|udp  |");
+    }
+
+    #[test]
+    fn snap_len_truncation() {
+        let mut udp = vec![0; 40];
+        udp[4..6].copy_from_slice(&40_u16.to_be_bytes());
+        let mut tcp = vec![0; 200];
+        tcp[12] = 5 << 4;
+        let mut writer = CaptureWriter::with_snap_len(vec![], RAW_IP, 96).unwrap();
+        for frame in [ipv4(17, 1, &udp), ipv4(6, 2, &tcp)] {
+            writer.write_frame(SystemTime::UNIX_EPOCH, &frame).unwrap();
+        }
+        let data = writer.into_inner().unwrap();
+
+        let pcap = Capture::new(&data).unwrap();
+        assert_eq!(pcap.snap_len(), 96);
+        let mut records = pcap.records();
+        let (short, long) = (records.next().unwrap(), records.next().unwrap());
+        assert_eq!((short.raw().len(), short.was_truncated()), (60, false));
+        assert_eq!((long.raw().len(), long.was_truncated()), (96, true));
+        assert!(records.next().is_none());
+
+        assert_eq!(short.ip().unwrap().udp_payload().unwrap().len(), 32);
+        // Read as far as it was captured, rather than rejected for its total length
+        let packet = long.ip().unwrap();
+        assert!(packet.was_truncated());
+        assert_eq!(packet.data.len(), 96 - 20);
+        assert_eq!(packet.declared_length, 200);
+        assert_eq!(packet.tcp_payload().unwrap().len(), 96 - 20 - 20);
+    }
+
+    #[test]
+    fn zero_snap_len() {
+        let mut data = capture(&[&[0; 20]]);
+        data[16..20].copy_from_slice(&0_u32.to_le_bytes());
+        assert_eq!(Capture::new(&data).err(), Some(CaptureError::ZeroSnapLen));
+        data[16..20].copy_from_slice(&1_u32.to_le_bytes());
+        assert_eq!(Capture::new(&data).unwrap().snap_len(), 1);
+    }
+
+    #[test]
+    fn truncated_headers() {
+        const ETHERNET: u32 = 1;
+        let packet = ipv4(17, 1, &[0; 40]);
+        let mut frame = vec![0; 12];
+        frame.extend([0x08, 0x00]);
+        frame.extend(&packet);
+
+        // Cut off partway through the IP header, and partway through the Ethernet header
+        for (link_type, frame, snap_len) in [(ETHERNET, &frame, 20), (ETHERNET, &frame, 12), (RAW_IP, &packet, 10)] {
+            let mut writer = CaptureWriter::with_snap_len(vec![], link_type, snap_len).unwrap();
+            writer.write_frame(SystemTime::UNIX_EPOCH, frame).unwrap();
+            let data = writer.into_inner().unwrap();
+            let pcap = Capture::new(&data).unwrap();
+            let frame = pcap.records().next().unwrap();
+            assert!(frame.was_truncated());
+            assert_eq!(frame.ip().err(), Some(CaptureError::FrameTooShort(snap_len as usize)));
+        }
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        // A full-size frame with a trailer after the IP packet, e.g. a frame check sequence
+        let mut frame = ipv4(17, 1, &[0; 200]);
+        frame.extend([0xAA; 4]);
+        let data = capture(&[&frame]);
+        let pcap = Capture::new(&data).unwrap();
+        let packet = pcap.records().next().unwrap().ip().unwrap();
+        assert_eq!(packet.data, [0; 200]);
+        assert!(!packet.was_truncated());
+    }
+
+    #[test]
+    fn truncated_tail() {
+        let frames: [&[u8]; 3] = [&[1; 20], &[2; 30], &[3; 40]];
+        let data = capture(&frames);
+        let last_record = data.len() - (16 + 40);
+        // Partway through the last record's header, and partway through its data
+        for end in [last_record + 1, last_record + 15, last_record + 16, data.len() - 1] {
+            let pcap = Capture::new(&data[..end]).unwrap();
+            let records = pcap.records().map(|frame| frame.raw().to_vec()).collect::<Vec<_>>();
+            assert_eq!(records, frames[..2], "truncated at {end}");
+        }
+        let pcap = Capture::new(&data).unwrap();
+        assert_eq!(pcap.records().count(), 3);
+    }
+
+    #[test]
+    fn prefix() {
+        let frames: [&[u8]; 3] = [&[1; 20], &[2; 30], &[3; 40]];
+        let data = capture(&frames);
+        let pcap = Capture::new(&data).unwrap();
+        assert_eq!(pcap.prefix(0), &data[..24]);
+        assert_eq!(pcap.prefix(2), &data[..24 + 16 + 20 + 16 + 30]);
+        assert_eq!(pcap.prefix(3), data);
+        assert_eq!(pcap.prefix(4), data);
+    }
+
+    #[test]
+    fn records_in() {
+        let frames: [&[u8]; 3] = [&[1; 20], &[2; 30], &[3; 40]];
+        let data = capture(&frames);
+        let (header, rest) = data.split_at(Capture::HEADER_LENGTH);
+        let pcap = Capture::new(header).unwrap();
+        assert_eq!(pcap.records().count(), 0);
+
+        // Stops before the partial record at the end, leaving its position at that record's start
+        let mut records = pcap.records_in(&rest[..rest.len() - 10]);
+        let read = records.by_ref().map(|frame| frame.raw().to_vec()).collect::<Vec<_>>();
+        assert_eq!(read, frames[..2]);
+        assert_eq!(records.error(), None);
+        assert_eq!(records.position(), 16 + 20 + 16 + 30);
+
+        let mut records = pcap.records_in(&rest[records.position()..]);
+        assert_eq!(records.next().unwrap().raw(), frames[2]);
+        assert_eq!(records.position(), 16 + 40);
+        assert_eq!(pcap.records_in(&[]).count(), 0);
+    }
+
+    #[test]
+    fn bad_record_length() {
+        let mut data = capture(&[&[1; 20], &[2; 30], &[3; 40]]);
+        // The second record's original length, now shorter than the 30 bytes captured
+        let original = 24 + 16 + 20 + 12;
+        data[original..original + 4].copy_from_slice(&29_u32.to_le_bytes());
+        let pcap = Capture::new(&data).unwrap();
+
+        let mut records = pcap.records();
+        assert_eq!(records.by_ref().map(|frame| frame.raw().len()).collect::<Vec<_>>(), [20]);
+        assert_eq!(records.error(), Some(CaptureError::BadRecordLength(30, 29)));
+        assert_eq!(records.next().map(|frame| frame.raw().len()), None);
+
+        assert!(pcap.time_range().is_err());
+        assert_eq!(pcap.sample(1).count(), 1);
+    }
+
+    #[test]
+    fn oversized_microseconds() {
+        let mut data = capture(&[&[0; 20]]);
+        // The record's microseconds, just after its seconds
+        data[24 + 4..24 + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let pcap = Capture::new(&data).unwrap();
+        let timestamp = pcap.records().next().unwrap().timestamp();
+        assert_eq!(timestamp, SystemTime::UNIX_EPOCH + Duration::from_micros(u32::MAX.into()));
+    }
+}
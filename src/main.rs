@@ -1,58 +1,529 @@
-use std::{collections::HashMap, time::Instant};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{IsTerminal, Read},
+    net::IpAddr,
+    time::{Duration, Instant, SystemTime},
+};
 
-use packet_storm::{IpPacket, Protocol};
+use anyhow::{anyhow, bail, Context};
+use packet_storm::{Capture, CaptureError, IpPacket, PcapNg, Protocol, Summary};
+
+const USAGE: &str = "\
+Usage: packet-storm [--json | --csv | --prometheus] [--udp-only] [--duplicates] [--top N] [--approximate N] [--quiet] [PATH...]
+       packet-storm --follow [--udp-only] PATH
+       packet-storm diff BEFORE AFTER
+
+Reads the captures from each PATH and reports combined statistics, reading stdin for a PATH of `-`.
+With no PATH, stdin is used if it is not a terminal, otherwise `packet-storm.pcap`.
+`--prometheus` prints the statistics as Prometheus metrics, with a gauge per destination IP listed.
+`--top N` prints the N most frequent IPs, rather than the three most frequent tiers of counts.
+`--duplicates` also counts packets that are exact copies of an earlier one, e.g. retransmissions.
+`--approximate N` estimates the destination counts by tracking at most N IPs, using less memory.
+`--quiet` leaves out the time taken and the warnings for skipped captures.
+`--follow` keeps reading records as they're appended to a pcap capture (e.g. by `tcpdump -w`),
+printing running totals, until interrupted.
+`diff` compares how many packets went to each destination IP in two captures, biggest change first.";
 
 fn main() -> anyhow::Result<()> {
-    let path = std::env::args_os().nth(1).unwrap_or("packet-storm.pcap".into());
+    let mut paths = vec![];
+    let mut json = false;
+    let mut csv = false;
+    let mut prometheus = false;
+    let mut udp_only = false;
+    let mut duplicates = false;
+    let mut top = None;
+    let mut quiet = false;
+    let mut approximate = None;
+    let mut follow = false;
+    let mut args = std::env::args_os().skip(1).peekable();
+    if args.next_if(|arg| arg == "diff").is_some() {
+        let (Some(before), Some(after), None) = (args.next(), args.next(), args.next()) else {
+            bail!("diff needs exactly two paths\n\n{USAGE}");
+        };
+        return diff(&before, &after);
+    }
+    while let Some(arg) = args.next() {
+        if arg == "--json" {
+            json = true;
+        } else if arg == "--csv" {
+            csv = true;
+        } else if arg == "--prometheus" {
+            prometheus = true;
+        } else if arg == "--udp-only" {
+            udp_only = true;
+        } else if arg == "--duplicates" {
+            duplicates = true;
+        } else if arg == "--top" {
+            let n = args.next().ok_or_else(|| anyhow!("--top needs a count\n\n{USAGE}"))?;
+            let n = n.to_str().and_then(|n| n.parse().ok());
+            top = Some(n.ok_or_else(|| anyhow!("--top needs a count\n\n{USAGE}"))?);
+        } else if arg == "--approximate" {
+            let n = args.next().ok_or_else(|| anyhow!("--approximate needs a count\n\n{USAGE}"))?;
+            let n = n.to_str().and_then(|n| n.parse().ok()).filter(|&n| n > 0);
+            approximate = Some(n.ok_or_else(|| anyhow!("--approximate needs a nonzero count\n\n{USAGE}"))?);
+        } else if arg == "--follow" {
+            follow = true;
+        } else if arg == "--quiet" {
+            quiet = true;
+        } else if arg == "--help" {
+            println!("{USAGE}");
+            return Ok(());
+        } else {
+            paths.push(arg);
+        }
+    }
+    if [json, csv, prometheus].into_iter().filter(|&it| it).count() > 1 {
+        bail!("--json, --csv and --prometheus are mutually exclusive\n\n{USAGE}");
+    }
+    let filter = |packet: &IpPacket| !udp_only || matches!(packet.protocol, Protocol::UDP);
+    if follow {
+        if json || csv || prometheus {
+            bail!("--follow only prints plain text\n\n{USAGE}");
+        }
+        if top.is_some() || approximate.is_some() || duplicates || quiet {
+            bail!("--follow only supports --udp-only\n\n{USAGE}");
+        }
+        let [path] = &paths[..] else {
+            bail!("--follow needs exactly one path\n\n{USAGE}");
+        };
+        if path == "-" {
+            bail!("--follow needs a file, not stdin\n\n{USAGE}");
+        }
+        return follow_capture(path, filter);
+    }
+    if paths.is_empty() {
+        paths.push(if std::io::stdin().is_terminal() { "packet-storm.pcap" } else { "-" }.into());
+    }
 
-    let data = fs_err::read(path)?;
+    let mut taken = Duration::ZERO;
+    let mut time_range: Option<(SystemTime, SystemTime)> = None;
 
-    let start = Instant::now();
+    let mut summary = Summary::default();
+    // Replaces the summary's destinations while counting, if enabled
+    let mut approx_dest_ips = approximate.map(SpaceSaving::new);
+    let mut src_ips = HashMap::new();
+    let mut sizes = [0_usize; SIZE_BUCKETS];
+    // Only the hashes are kept, so memory is 8 bytes per distinct packet (plus the set's overhead)
+    // rather than the packets themselves, at the cost of a vanishingly small chance of a collision
+    let mut seen = duplicates.then(HashSet::new);
+    let mut no_duplicates = 0_usize;
+    // Frames that couldn't be read as TCP or UDP packets, e.g. ARP or ICMP
+    let mut skipped_frames = 0_usize;
 
-    let mut no_packets = 0_usize;
-    let mut total_transport_level_data = 0;
-    let mut dest_ips = HashMap::new();
-    let mut udp = 0_usize;
+    let warn = |message: String| {
+        if !quiet {
+            eprintln!("{message}");
+        }
+    };
+    for path in &paths {
+        let data = match read_input(path) {
+            Ok(data) => data,
+            Err(e) => {
+                warn(format!("Skipping {}: {e:#}", path.to_string_lossy()));
+                continue;
+            }
+        };
+
+        let start = Instant::now();
+
+        let mut pcap = None;
+        let (range, packets) = match ip_packets(&data, &mut pcap, filter) {
+            Ok(packets) => packets,
+            Err(e) => {
+                warn(format!("Skipping {}: {e:#}", path.to_string_lossy()));
+                continue;
+            }
+        };
+
+        if let Ok((first, last)) = range {
+            time_range = Some(time_range.map_or((first, last), |(start, end)| (start.min(first), end.max(last))));
+        }
+
+        for packet in packets {
+            let packet = match packet {
+                Ok(packet) => packet,
+                Err(e @ CaptureError::BadRecordLength(..)) => {
+                    warn(format!("Stopped reading {} early: {e}", path.to_string_lossy()));
+                    break;
+                }
+                Err(_) => {
+                    skipped_frames += 1;
+                    continue;
+                }
+            };
+            match &mut approx_dest_ips {
+                Some(approx) => {
+                    summary.add_totals(&packet);
+                    approx.add(packet.dest);
+                }
+                None => summary.add(&packet),
+            }
+            if let Some(seen) = &mut seen {
+                let mut hasher = DefaultHasher::new();
+                (packet.source, packet.dest, packet.protocol.as_u8(), packet.data).hash(&mut hasher);
+                if !seen.insert(hasher.finish()) {
+                    no_duplicates += 1;
+                }
+            }
+            sizes[size_bucket(packet.data.len())] += 1;
+            *src_ips.entry(packet.source).or_insert(0_usize) += 1;
+        }
+
+        taken += start.elapsed();
+    }
 
-    let pcap = packet_storm::Capture::new(&data)?;
+    if skipped_frames > 0 {
+        warn(format!("Skipped {skipped_frames} frames that aren't TCP or UDP over IP"));
+    }
+
+    let dest_ips = match approx_dest_ips {
+        Some(approx) => by_frequency(approx.into_counts()),
+        None => by_frequency(summary.destinations().to_vec()),
+    };
+    let src_ips = by_frequency(src_ips);
+    let select = |ips| match top {
+        Some(n) => top_n(ips, n),
+        None => top_tiers(ips, 3),
+    };
 
-    for record in pcap.records() {
-        let IpPacket {
-            data,
-            protocol,
-            source: _,
-            dest,
-        } = record.ip()?;
-        total_transport_level_data += data.len();
-        if matches!(protocol, Protocol::UDP) {
-            udp += 1;
+    // An empty capture has no average, rather than NaN (which JSON can only give as `null`). The
+    // other formats only have counts, which are just 0 or empty
+    let average = (summary.packets > 0).then(|| (summary.ip_data as f64) / (summary.packets as f64));
+
+    if json {
+        let to_json = |ips: &[(IpAddr, usize)]| {
+            ips.iter()
+                .map(|(ip, n)| serde_json::json!({ "ip": ip.to_string(), "count": n }))
+                .collect::<Vec<_>>()
+        };
+        let mut out = serde_json::json!({
+            "total_ip_data": summary.ip_data,
+            "tcp_payload_data": summary.tcp_payload_data,
+            "udp_payload_data": summary.udp_payload_data,
+            "udp_count": summary.udp_packets,
+            "tcp_count": summary.tcp_packets(),
+            "destinations": to_json(&dest_ips),
+            "sources": to_json(&src_ips),
+        });
+        if let Some(average) = average {
+            out["avg_bytes_per_packet"] = average.into();
         }
-        *dest_ips.entry(dest).or_insert(0_usize) += 1;
-        no_packets += 1;
+        if duplicates {
+            out["duplicate_count"] = no_duplicates.into();
+        }
+        if skipped_frames > 0 {
+            out["skipped_frames"] = skipped_frames.into();
+        }
+        if approximate.is_some() {
+            out["destinations_approximate"] = true.into();
+        }
+        println!("{out:#}");
+        return Ok(());
     }
 
-    let taken = start.elapsed();
-    println!("Took {taken:?}");
+    if prometheus {
+        let metric = |name: &str, kind: &str, help: &str, value: usize| {
+            println!("# HELP packet_storm_{name} {help}\n# TYPE packet_storm_{name} {kind}\npacket_storm_{name} {value}");
+        };
+        metric("packets_total", "counter", "IP packets read.", summary.packets);
+        metric("udp_packets_total", "counter", "UDP packets read.", summary.udp_packets);
+        metric("tcp_packets_total", "counter", "TCP packets read.", summary.tcp_packets());
+        metric("ip_bytes_total", "counter", "IP-level data, excluding IP headers.", summary.ip_data);
+        metric("tcp_payload_bytes_total", "counter", "Application-level data over TCP.", summary.tcp_payload_data);
+        metric("udp_payload_bytes_total", "counter", "Application-level data over UDP.", summary.udp_payload_data);
+        if duplicates {
+            metric("duplicate_packets_total", "counter", "Packets that exactly match an earlier one.", no_duplicates);
+        }
+        println!("# HELP packet_storm_destination_packets IP packets to each of the most frequent destinations.");
+        println!("# TYPE packet_storm_destination_packets gauge");
+        for (ip, n) in select(&dest_ips) {
+            println!("packet_storm_destination_packets{{ip=\"{ip}\"}} {n}");
+        }
+        return Ok(());
+    }
+
+    if csv {
+        println!("ip,count");
+        for (ip, n) in &dest_ips {
+            println!("{ip},{n}");
+        }
+        return Ok(());
+    }
+
+    if !quiet {
+        println!("Took {taken:?}");
+    }
 
-    println!("Total IP-level data: {} bytes", total_transport_level_data);
-    println!("{} UDP, {} TCP", udp, no_packets - udp);
+    let Some(average) = average else {
+        // Rather than empty lists
+        println!("Capture contains no IP packets");
+        return Ok(());
+    };
+
+    println!("Total IP-level data: {} bytes", summary.ip_data);
     println!(
-        "Average of {:.2} bytes/packet",
-        (total_transport_level_data as f64) / (no_packets as f64)
+        "Application-level data: {} bytes over TCP, {} bytes over UDP",
+        summary.tcp_payload_data, summary.udp_payload_data
     );
-    let mut ips = dest_ips.into_iter().collect::<Vec<_>>();
+    println!("{} UDP, {} TCP", summary.udp_packets, summary.tcp_packets());
+    if duplicates {
+        println!("{no_duplicates} duplicate packets");
+    }
+    println!("Average of {average:.2} bytes/packet");
+    if let Some((start, end)) = time_range {
+        let span = end.duration_since(start).unwrap_or_default().as_secs_f64();
+        println!("Capture spans {span:.2} seconds");
+        // A single packet (or several with the same timestamp) has no rate
+        let rate = |n: usize| if span > 0.0 { format!("{:.2}", n as f64 / span) } else { "n/a".to_owned() };
+        println!(
+            "Average of {} packets/second, {} bytes/second",
+            rate(summary.packets),
+            rate(summary.ip_data)
+        );
+    }
+    print_size_histogram(&sizes);
+    if let Some(n) = approximate {
+        println!("Destination counts are estimates, from tracking at most {n} IPs");
+    }
+    print_top("Destination", &dest_ips, select(&dest_ips));
+    print_top("Source", &src_ips, select(&src_ips));
+
+    Ok(())
+}
+
+// The IP packets in either a pcap or pcapng capture, along with its time range. The records borrow
+// the capture, so `pcap` holds it for as long as the packets are needed. Frames that aren't IP
+// packets are kept as errors, so the caller can count them, while `filter` only drops packets. A
+// malformed record ends the packets with its `CaptureError::BadRecordLength`.
+type IpPackets<'a> = (
+    anyhow::Result<(SystemTime, SystemTime)>,
+    Box<dyn Iterator<Item = Result<IpPacket<'a>, CaptureError>> + 'a>,
+);
+
+fn ip_packets<'a>(
+    data: &'a [u8],
+    pcap: &'a mut Option<Capture<'a>>,
+    filter: impl Fn(&IpPacket<'a>) -> bool + 'a,
+) -> anyhow::Result<IpPackets<'a>> {
+    if PcapNg::is_pcapng(data) {
+        let pcapng = PcapNg::new(data)?;
+        let packets = pcapng.records().map(|frame| frame.ip());
+        Ok((pcapng.time_range(), Box::new(packets.filter(move |packet| packet.as_ref().map_or(true, &filter)))))
+    } else {
+        let pcap = pcap.insert(Capture::new(data)?);
+        let mut records = pcap.records();
+        let mut ended = false;
+        let packets = std::iter::from_fn(move || match records.next() {
+            Some(frame) => Some(frame.ip()),
+            None if !ended => {
+                ended = true;
+                records.error().map(Err)
+            }
+            None => None,
+        });
+        Ok((pcap.time_range(), Box::new(packets.filter(move |packet| packet.as_ref().map_or(true, &filter)))))
+    }
+}
+
+fn dest_counts(path: &OsStr) -> anyhow::Result<HashMap<IpAddr, usize>> {
+    let data = read_input(path).with_context(|| format!("failed to read {}", path.to_string_lossy()))?;
+    let mut pcap = None;
+    let (_, packets) = ip_packets(&data, &mut pcap, |_| true).with_context(|| format!("failed to parse {}", path.to_string_lossy()))?;
+    let mut dest_ips = HashMap::new();
+    let mut skipped = 0_usize;
+    for packet in packets {
+        match packet {
+            Ok(packet) => *dest_ips.entry(packet.dest).or_insert(0_usize) += 1,
+            Err(e @ CaptureError::BadRecordLength(..)) => bail!("failed to parse {}: {e}", path.to_string_lossy()),
+            Err(_) => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        eprintln!("Skipped {skipped} frames in {} that aren't TCP or UDP over IP", path.to_string_lossy());
+    }
+    Ok(dest_ips)
+}
+
+fn diff(before: &OsStr, after: &OsStr) -> anyhow::Result<()> {
+    let before = dest_counts(before)?;
+    let after = dest_counts(after)?;
+
+    let mut changes = before
+        .keys()
+        .chain(after.keys().filter(|ip| !before.contains_key(ip)))
+        .map(|&ip| (ip, before.get(&ip).copied().unwrap_or(0), after.get(&ip).copied().unwrap_or(0)))
+        .filter(|(_, before, after)| before != after)
+        .collect::<Vec<_>>();
+    // Ties are ordered by address, so the output is the same between runs
+    changes.sort_by_key(|&(ip, before, after)| (std::cmp::Reverse(before.abs_diff(after)), ip));
+
+    if changes.is_empty() {
+        println!("No change in packets per destination IP");
+        return Ok(());
+    }
+    for (ip, before, after) in changes {
+        let delta = after as i64 - before as i64;
+        let kind = match (before, after) {
+            (0, _) => "Added",
+            (_, 0) => "Removed",
+            _ => "Changed",
+        };
+        println!("{kind:7} {ip:15} - {before} -> {after} ({delta:+})");
+    }
+    Ok(())
+}
+
+// How long `--follow` waits before checking for new records
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+fn follow_capture(path: &OsStr, filter: impl Fn(&IpPacket) -> bool) -> anyhow::Result<()> {
+    let mut file = fs_err::File::open(path)?;
+    // Wait for the whole header before looking at it
+    let mut header = vec![];
+    while header.len() < Capture::HEADER_LENGTH {
+        if (&mut file).take((Capture::HEADER_LENGTH - header.len()) as u64).read_to_end(&mut header)? == 0 {
+            std::thread::sleep(FOLLOW_INTERVAL);
+        }
+    }
+    if PcapNg::is_pcapng(&header) {
+        bail!("--follow only supports pcap captures");
+    }
+    let capture = Capture::new(&header)?;
+
+    // Whatever hasn't been processed yet, i.e. a record that's still being written
+    let mut pending = vec![];
+    let mut summary = Summary::default();
+    loop {
+        // Reading on from the end of the file picks up anything appended since
+        if file.read_to_end(&mut pending)? == 0 {
+            std::thread::sleep(FOLLOW_INTERVAL);
+            continue;
+        }
+
+        let mut new_packets = 0;
+        // Stops before a partial record, which is left for the next time round
+        let mut records = capture.records_in(&pending);
+        for frame in records.by_ref() {
+            let Ok(packet) = frame.ip() else {
+                continue;
+            };
+            if !filter(&packet) {
+                continue;
+            }
+            summary.add(&packet);
+            new_packets += 1;
+        }
+        if let Some(e) = records.error() {
+            bail!(e);
+        }
+        let consumed = records.position();
+        pending.drain(..consumed);
+
+        if new_packets > 0 {
+            let (ip, n) = summary.destinations().iter().max_by_key(|&(_, n)| n).unwrap();
+            println!(
+                "{} packets ({} UDP, {} TCP), {} bytes of IP-level data, most popular destination {ip} with {n}",
+                summary.packets,
+                summary.udp_packets,
+                summary.tcp_packets(),
+                summary.ip_data
+            );
+        }
+    }
+}
+
+fn read_input(path: &OsStr) -> anyhow::Result<Vec<u8>> {
+    let data = if path == "-" {
+        let mut data = vec![];
+        std::io::stdin().lock().read_to_end(&mut data)?;
+        data
+    } else {
+        fs_err::read(path)?
+    };
+    Capture::decompress(data)
+}
+
+const SIZE_BUCKET_WIDTH: usize = 64;
+// The last bucket also takes everything larger, i.e. 512+
+const SIZE_BUCKETS: usize = 9;
+
+fn size_bucket(len: usize) -> usize {
+    (len / SIZE_BUCKET_WIDTH).min(SIZE_BUCKETS - 1)
+}
+
+fn print_size_histogram(sizes: &[usize; SIZE_BUCKETS]) {
+    const BAR_WIDTH: usize = 40;
+
+    let max = sizes.iter().copied().max().unwrap_or(0).max(1);
+    println!("Packet sizes:");
+    for (i, &n) in sizes.iter().enumerate() {
+        let start = i * SIZE_BUCKET_WIDTH;
+        let range = if i == SIZE_BUCKETS - 1 {
+            format!("{start}+")
+        } else {
+            format!("{start}-{}", start + SIZE_BUCKET_WIDTH - 1)
+        };
+        println!("{range:>7} | {:<BAR_WIDTH$} {n}", "#".repeat((n * BAR_WIDTH).div_ceil(max)));
+    }
+}
+
+// The Space-Saving algorithm, which counts the most frequent IPs in bounded memory. Once full, a new
+// IP replaces the one with the lowest count and takes over its count, so counts are overestimates
+// by at most the lowest count when they were added (always at most `total / capacity`). Any IP seen
+// more often than that is guaranteed to be kept, so the top of the list is reliable.
+struct SpaceSaving {
+    capacity: usize,
+    counts: HashMap<IpAddr, usize>,
+}
+
+impl SpaceSaving {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counts: HashMap::with_capacity(capacity),
+        }
+    }
+
+    fn add(&mut self, ip: IpAddr) {
+        if let Some(count) = self.counts.get_mut(&ip) {
+            *count += 1;
+        } else if self.counts.len() < self.capacity {
+            self.counts.insert(ip, 1);
+        } else {
+            // A linear scan, so this is slow for a large `capacity` with many distinct IPs. Ties
+            // go to the lowest IP, so the results don't depend on the map's order.
+            let (&evicted, &min) = self.counts.iter().min_by_key(|&(ip, n)| (*n, *ip)).unwrap();
+            self.counts.remove(&evicted);
+            self.counts.insert(ip, min + 1);
+        }
+    }
+
+    fn into_counts(self) -> HashMap<IpAddr, usize> {
+        self.counts
+    }
+}
+
+fn by_frequency(counts: impl IntoIterator<Item = (IpAddr, usize)>) -> Vec<(IpAddr, usize)> {
+    let mut ips = counts.into_iter().collect::<Vec<_>>();
     ips.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
-    let (most_popular, taken) = ips
+    ips
+}
+
+// `ips` must be sorted by descending frequency
+fn top_tiers(ips: &[(IpAddr, usize)], tiers: usize) -> &[(IpAddr, usize)] {
+    let taken = ips
         .iter()
         .scan((None, 0), |(prev, count), it| {
-            // Selects the first three most popular tiers of destinations, i.e. all 16 counts,
+            // Selects the first `tiers` most popular tiers of addresses, e.g. all 16 counts,
             // all 15s, all 14s.
             if let Some(prev) = prev {
                 if *prev != it.1 {
                     *count += 1;
                     *prev = it.1;
                 }
-                if *count >= 3 {
+                if *count >= tiers {
                     None
                 } else {
                     Some(it)
@@ -62,15 +533,23 @@ fn main() -> anyhow::Result<()> {
                 Some(it)
             }
         })
-        .fold((String::new(), 0_usize), |(mut acc, taken), (ip, n)| {
-            use std::fmt::Write as _;
-            let _ = writeln!(acc, "{ip:15} - {n}");
-            (acc, taken + 1)
-        });
+        .count();
+    &ips[..taken]
+}
+
+// `ips` must be sorted by descending frequency
+fn top_n(ips: &[(IpAddr, usize)], n: usize) -> &[(IpAddr, usize)] {
+    &ips[..n.min(ips.len())]
+}
+
+fn print_top(kind: &str, ips: &[(IpAddr, usize)], top: &[(IpAddr, usize)]) {
+    let most_popular = top.iter().fold(String::new(), |mut acc, (ip, n)| {
+        use std::fmt::Write as _;
+        let _ = writeln!(acc, "{ip:15} - {n}");
+        acc
+    });
     println!(
-        "Destination IPs by frequency:\n{most_popular}...and {} more entries",
-        ips.len() - taken
+        "{kind} IPs by frequency:\n{most_popular}...and {} more entries",
+        ips.len() - top.len()
     );
-
-    Ok(())
 }
@@ -0,0 +1,426 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+
+use crate::{CaptureError, LinkType, PhysicalFrame, Readable};
+
+/// A capture in the pcapng format, the default for Wireshark and other newer tools.
+///
+/// Only enough of the format to read packets is supported, i.e. Section Header, Interface
+/// Description and Enhanced Packet Blocks. Name resolution, statistics, decryption secret and
+/// custom blocks are skipped, and any other block (e.g. a Simple Packet Block) is an error.
+pub struct PcapNg<'a> {
+    data: &'a [u8],
+}
+
+const SECTION_HEADER: u32 = 0x0A0D0D0A;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const NAME_RESOLUTION: u32 = 4;
+const INTERFACE_STATISTICS: u32 = 5;
+const ENHANCED_PACKET: u32 = 6;
+const DECRYPTION_SECRETS: u32 = 0x0A;
+const CUSTOM: u32 = 0x0BAD;
+const CUSTOM_NO_COPY: u32 = 0x40000BAD;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+
+impl<'a> PcapNg<'a> {
+    /// Whether `data` starts with a pcapng Section Header Block, rather than a pcap header.
+    pub fn is_pcapng(data: &[u8]) -> bool {
+        // The block type is the same either way round, so the byte order doesn't matter yet
+        data.starts_with(&SECTION_HEADER.to_le_bytes())
+    }
+
+    /// Checks every block up front, so iterating over the records afterwards can't fail.
+    pub fn new(data: &'a [u8]) -> Result<Self, CaptureError> {
+        if data.len() < 3 * u32::BYTES {
+            return Err(CaptureError::TruncatedHeader);
+        }
+        if !Self::is_pcapng(data) {
+            let mut position = 0;
+            return Err(CaptureError::BadMagic(u32::read_le(data, &mut position)));
+        }
+        let mut records = NgRecords::new(data);
+        while records.next_frame()?.is_some() {}
+        Ok(Self { data })
+    }
+
+    /// The timestamps of the first and last records, which is an error for an empty capture.
+    pub fn time_range(&self) -> anyhow::Result<(SystemTime, SystemTime)> {
+        let mut records = self.records();
+        let first = records.next().ok_or_else(|| anyhow!("capture has no records"))?.timestamp();
+        let last = records.last().map_or(first, |last| last.timestamp());
+        Ok((first, last))
+    }
+
+    pub fn records(&self) -> NgRecords<'a> {
+        NgRecords::new(self.data)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Interface {
+    link_type: LinkType,
+    // How many timestamp units there are in a second, from the `if_tsresol` option
+    units_per_second: u64,
+}
+
+pub struct NgRecords<'a> {
+    data: &'a [u8],
+    position: usize,
+    big_endian: bool,
+    // Indexed by the interface ID in each packet, and reset by each section
+    interfaces: Vec<Interface>,
+}
+
+impl<'a> NgRecords<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: 0,
+            big_endian: false,
+            interfaces: vec![],
+        }
+    }
+
+    fn read<T: Readable>(&self, data: &[u8], position: &mut usize) -> T {
+        if self.big_endian {
+            T::read_be(data, position)
+        } else {
+            T::read_le(data, position)
+        }
+    }
+
+    // The next packet, skipping over any other blocks, or `None` at the end of the capture
+    fn next_frame(&mut self) -> Result<Option<PhysicalFrame<'a>>, CaptureError> {
+        loop {
+            // As with pcap, a partial block at the end (e.g. from an interrupted capture) is
+            // treated as the end of the capture
+            if self.position + 3 * u32::BYTES > self.data.len() {
+                return Ok(None);
+            }
+            let mut position = self.position;
+            let block_type = self.read::<u32>(self.data, &mut position);
+            if block_type == SECTION_HEADER {
+                // Each section can have a different byte order, which includes its block lengths
+                let mut magic_position = position + u32::BYTES;
+                self.big_endian = match u32::read_le(self.data, &mut magic_position) {
+                    BYTE_ORDER_MAGIC => false,
+                    magic if magic.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                    magic => return Err(CaptureError::BadMagic(magic)),
+                };
+            }
+            let length = self.read::<u32>(self.data, &mut position);
+            if length < 3 * u32::BYTES as u32 || !length.is_multiple_of(4) {
+                return Err(CaptureError::BadBlockLength(length));
+            }
+            let Some(end) = self.position.checked_add(length as usize).filter(|&end| end <= self.data.len()) else {
+                return Ok(None);
+            };
+            let body = &self.data[position..end - u32::BYTES];
+            self.position = end;
+
+            match block_type {
+                SECTION_HEADER => {
+                    if body.len() < 4 * u32::BYTES {
+                        return Err(CaptureError::BadBlockLength(length));
+                    }
+                    let mut position = u32::BYTES; // Byte-order magic
+                    let version = (self.read::<u16>(body, &mut position), self.read::<u16>(body, &mut position));
+                    if version.0 != 1 {
+                        return Err(CaptureError::UnsupportedVersion(version.0, version.1));
+                    }
+                    self.interfaces.clear();
+                }
+                INTERFACE_DESCRIPTION => {
+                    if body.len() < 2 * u32::BYTES {
+                        return Err(CaptureError::BadBlockLength(length));
+                    }
+                    let mut position = 0;
+                    let link_type = match self.read::<u16>(body, &mut position) {
+                        1 => LinkType::Ethernet,
+                        101 => LinkType::RawIp,
+                        other => return Err(CaptureError::UnsupportedLinkType(other.into())),
+                    };
+                    position += u16::BYTES; // Reserved
+                    position += u32::BYTES; // Snap length
+                    let units_per_second = self.timestamp_resolution(body, position)?;
+                    self.interfaces.push(Interface {
+                        link_type,
+                        units_per_second,
+                    });
+                }
+                ENHANCED_PACKET => {
+                    if body.len() < 5 * u32::BYTES {
+                        return Err(CaptureError::BadBlockLength(length));
+                    }
+                    let mut position = 0;
+                    let interface_id = self.read::<u32>(body, &mut position);
+                    let interface = *self
+                        .interfaces
+                        .get(interface_id as usize)
+                        .ok_or(CaptureError::UnknownInterface(interface_id))?;
+                    let high = self.read::<u32>(body, &mut position) as u64;
+                    let low = self.read::<u32>(body, &mut position) as u64;
+                    let captured = self.read::<u32>(body, &mut position);
+                    let original = self.read::<u32>(body, &mut position);
+                    if captured > original {
+                        return Err(CaptureError::BadRecordLength(captured, original));
+                    }
+                    let data = body
+                        .get(position..position + captured as usize)
+                        .ok_or(CaptureError::BadBlockLength(length))?;
+
+                    let units = (high << 32) | low;
+                    let per_second = interface.units_per_second;
+                    let nanos = (units % per_second) as u128 * 1_000_000_000 / per_second as u128;
+                    return Ok(Some(PhysicalFrame {
+                        data,
+                        link_type: interface.link_type,
+                        timestamp: Duration::new(units / per_second, nanos as u32),
+                        truncated: captured < original,
+                    }));
+                }
+                NAME_RESOLUTION | INTERFACE_STATISTICS | DECRYPTION_SECRETS | CUSTOM | CUSTOM_NO_COPY => {}
+                other => return Err(CaptureError::UnsupportedBlockType(other)),
+            }
+        }
+    }
+
+    // Reads the `if_tsresol` option from an Interface Description Block's options, which
+    // defaults to microseconds
+    fn timestamp_resolution(&self, body: &[u8], mut position: usize) -> Result<u64, CaptureError> {
+        const END_OF_OPTIONS: u16 = 0;
+        const TIMESTAMP_RESOLUTION: u16 = 9;
+
+        while position + 2 * u16::BYTES <= body.len() {
+            let code = self.read::<u16>(body, &mut position);
+            let length = self.read::<u16>(body, &mut position) as usize;
+            if code == END_OF_OPTIONS {
+                break;
+            }
+            if code == TIMESTAMP_RESOLUTION && length == 1 && position < body.len() {
+                let resolution = body[position];
+                // The top bit picks between a negative power of 2 or of 10
+                let (base, exponent) = if resolution & 0x80 != 0 { (2_u64, resolution & 0x7F) } else { (10, resolution) };
+                return base
+                    .checked_pow(exponent.into())
+                    .ok_or(CaptureError::UnsupportedTimestampResolution(resolution));
+            }
+            // Values are padded to a multiple of 4 bytes
+            position += length.next_multiple_of(4);
+        }
+        Ok(1_000_000)
+    }
+}
+
+impl<'a> Iterator for NgRecords<'a> {
+    type Item = PhysicalFrame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().expect("blocks are checked by `PcapNg::new`")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETHERNET: u16 = 1;
+    const RAW_IP: u16 = 101;
+
+    // A pcapng capture built a block at a time, each in the byte order of its section
+    struct Blocks {
+        data: Vec<u8>,
+        big_endian: bool,
+    }
+
+    impl Blocks {
+        fn new(big_endian: bool) -> Self {
+            Self { data: vec![], big_endian }.section(big_endian)
+        }
+
+        fn u16(&self, n: u16) -> [u8; 2] {
+            if self.big_endian { n.to_be_bytes() } else { n.to_le_bytes() }
+        }
+
+        fn u32(&self, n: u32) -> [u8; 4] {
+            if self.big_endian { n.to_be_bytes() } else { n.to_le_bytes() }
+        }
+
+        // Pads the body to a multiple of 4 bytes, as the format requires
+        fn block(mut self, block_type: u32, body: &[u8]) -> Self {
+            let length = self.u32((3 * u32::BYTES + body.len().next_multiple_of(4)) as u32);
+            self.data.extend(self.u32(block_type));
+            self.data.extend(length);
+            self.data.extend(body);
+            self.data.resize(self.data.len() + body.len().next_multiple_of(4) - body.len(), 0);
+            self.data.extend(length);
+            self
+        }
+
+        fn section(mut self, big_endian: bool) -> Self {
+            self.big_endian = big_endian;
+            let mut body = self.u32(BYTE_ORDER_MAGIC).to_vec();
+            body.extend(self.u16(1));
+            body.extend(self.u16(0));
+            body.extend(u64::MAX.to_le_bytes()); // Unspecified section length
+            self.block(SECTION_HEADER, &body)
+        }
+
+        // With a comment option before any `if_tsresol`, to check other options are skipped
+        fn interface(self, link_type: u16, resolution: Option<u8>) -> Self {
+            let mut body = self.u16(link_type).to_vec();
+            body.extend(self.u16(0));
+            body.extend(self.u32(65535));
+            body.extend(self.u16(1));
+            body.extend(self.u16(5));
+            body.extend(b"hello\0\0\0");
+            if let Some(resolution) = resolution {
+                body.extend(self.u16(9));
+                body.extend(self.u16(1));
+                body.extend([resolution, 0, 0, 0]);
+            }
+            body.extend(self.u16(0));
+            body.extend(self.u16(0));
+            self.block(INTERFACE_DESCRIPTION, &body)
+        }
+
+        fn packet(self, interface: u32, units: u64, data: &[u8], original: u32) -> Self {
+            let mut body = self.u32(interface).to_vec();
+            body.extend(self.u32((units >> 32) as u32));
+            body.extend(self.u32(units as u32));
+            body.extend(self.u32(data.len() as u32));
+            body.extend(self.u32(original));
+            body.extend(data);
+            self.block(ENHANCED_PACKET, &body)
+        }
+    }
+
+    fn seconds(frame: &PhysicalFrame) -> f64 {
+        frame.timestamp().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs_f64()
+    }
+
+    #[test]
+    fn byte_order() {
+        for big_endian in [false, true] {
+            let data = Blocks::new(big_endian).interface(RAW_IP, None).packet(0, 2_500_000, &[1, 2, 3], 3).data;
+            assert!(PcapNg::is_pcapng(&data));
+            let pcap = PcapNg::new(&data).unwrap();
+            let frames = pcap.records().collect::<Vec<_>>();
+            assert_eq!(frames.len(), 1);
+            assert_eq!(frames[0].raw(), [1, 2, 3]);
+            assert_eq!(seconds(&frames[0]), 2.5);
+        }
+
+        // Each section has its own byte order, and its own interfaces
+        let data = Blocks::new(false)
+            .interface(RAW_IP, None)
+            .packet(0, 1_000_000, &[1], 1)
+            .section(true)
+            .interface(ETHERNET, None)
+            .packet(0, 2_000_000, &[2], 1)
+            .data;
+        let frames = PcapNg::new(&data).unwrap().records().collect::<Vec<_>>();
+        assert!(matches!(frames[0].link_type, LinkType::RawIp));
+        assert!(matches!(frames[1].link_type, LinkType::Ethernet));
+        assert_eq!(frames.iter().map(seconds).collect::<Vec<_>>(), [1.0, 2.0]);
+
+        let mut data = Blocks::new(false).data;
+        data[8..12].copy_from_slice(&0x12345678_u32.to_le_bytes());
+        assert_eq!(PcapNg::new(&data).err(), Some(CaptureError::BadMagic(0x12345678)));
+        // Not a pcapng capture at all, e.g. a pcap header
+        assert_eq!(PcapNg::new(&[0xD4, 0xC3, 0xB2, 0xA1, 0, 0, 0, 0, 0, 0, 0, 0]).err(), Some(CaptureError::BadMagic(0xA1B2C3D4)));
+    }
+
+    #[test]
+    fn interface_description() {
+        let data = Blocks::new(false)
+            .interface(ETHERNET, Some(3))
+            .interface(RAW_IP, Some(0x80 | 10))
+            .interface(RAW_IP, Some(9))
+            .packet(0, 1500, &[], 0)
+            .packet(1, 1536, &[], 0)
+            .packet(2, 1_500_000_001, &[0; 4], 10)
+            .data;
+        let frames = PcapNg::new(&data).unwrap().records().collect::<Vec<_>>();
+        assert!(matches!(frames[0].link_type, LinkType::Ethernet));
+        assert!(matches!(frames[1].link_type, LinkType::RawIp));
+        // Milliseconds, then 1/1024ths of a second, then nanoseconds
+        assert_eq!(frames[0].timestamp, Duration::from_millis(1500));
+        assert_eq!(frames[1].timestamp, Duration::from_millis(1500));
+        assert_eq!(frames[2].timestamp, Duration::new(1, 500_000_001));
+        assert!(!frames[1].was_truncated());
+        assert!(frames[2].was_truncated());
+
+        let data = Blocks::new(false).interface(105, None).data;
+        assert_eq!(PcapNg::new(&data).err(), Some(CaptureError::UnsupportedLinkType(105)));
+        let data = Blocks::new(false).interface(RAW_IP, Some(100)).data;
+        assert_eq!(PcapNg::new(&data).err(), Some(CaptureError::UnsupportedTimestampResolution(100)));
+        let data = Blocks::new(false).interface(RAW_IP, None).packet(1, 0, &[], 0).data;
+        assert_eq!(PcapNg::new(&data).err(), Some(CaptureError::UnknownInterface(1)));
+    }
+
+    #[test]
+    fn skipped_blocks() {
+        let data = Blocks::new(true)
+            .block(NAME_RESOLUTION, &[0; 4])
+            .interface(RAW_IP, None)
+            .packet(0, 0, &[1], 1)
+            .block(INTERFACE_STATISTICS, &[0; 12])
+            .block(DECRYPTION_SECRETS, &[0; 8])
+            .block(CUSTOM, &[0; 5])
+            .block(CUSTOM_NO_COPY, &[])
+            .packet(0, 0, &[2], 1)
+            .data;
+        let frames = PcapNg::new(&data).unwrap().records().map(|frame| frame.raw()).collect::<Vec<_>>();
+        assert_eq!(frames, [[1], [2]]);
+    }
+
+    #[test]
+    fn unsupported_block() {
+        // A Simple Packet Block
+        let data = Blocks::new(false).interface(RAW_IP, None).block(3, &[0, 0, 0, 1, 1]).data;
+        assert_eq!(PcapNg::new(&data).err(), Some(CaptureError::UnsupportedBlockType(3)));
+    }
+
+    #[test]
+    fn partial_trailing_block() {
+        let full = Blocks::new(false).interface(RAW_IP, None).packet(0, 0, &[1], 1).packet(0, 0, &[2; 8], 8).data;
+        // Cut off partway through the last packet's 40-byte block, and then before its length is
+        // even known
+        for cut in [4, 32] {
+            let data = &full[..full.len() - cut];
+            let frames = PcapNg::new(data).unwrap().records().map(|frame| frame.raw()).collect::<Vec<_>>();
+            assert_eq!(frames, [[1]]);
+        }
+    }
+
+    // `NgRecords::next` can only `expect` its blocks to be valid because `PcapNg::new` has already
+    // walked all of them, so an error after any number of good packets has to be caught there
+    #[test]
+    fn checked_up_front() {
+        let good = || Blocks::new(false).interface(RAW_IP, None).packet(0, 0, &[1], 1).packet(0, 0, &[2], 1);
+        let mut bad_length = good().data;
+        bad_length.extend([6, 0, 0, 0, 13, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let cases = [
+            (good().packet(0, 0, &[3], 1).packet(7, 0, &[], 0).data, CaptureError::UnknownInterface(7)),
+            (good().packet(0, 0, &[0; 4], 2).data, CaptureError::BadRecordLength(4, 2)),
+            (good().block(2, &[]).data, CaptureError::UnsupportedBlockType(2)),
+            (bad_length, CaptureError::BadBlockLength(13)),
+        ];
+        for (data, error) in cases {
+            let mut records = NgRecords::new(&data);
+            let mut packets = 0;
+            let end = loop {
+                match records.next_frame() {
+                    Ok(Some(_)) => packets += 1,
+                    end => break end,
+                }
+            };
+            assert!(packets >= 2);
+            assert_eq!(end.err(), Some(error));
+            assert_eq!(PcapNg::new(&data).err(), Some(error));
+        }
+    }
+}
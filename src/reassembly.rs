@@ -0,0 +1,254 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use crate::{IpPacket, OwnedIpPacket};
+
+/// Puts fragmented IP packets back together, so their payloads can be counted (or parsed) whole.
+///
+/// Fragments can arrive in any order, and duplicates (e.g. from retransmission) are harmless. A
+/// packet that isn't complete within the timeout of its first fragment arriving is dropped. That
+/// includes one with a fragment truncated by the capture, which leaves a gap that can never be
+/// filled, but is still only dropped once the timeout runs out.
+pub struct Reassembler {
+    timeout: Duration,
+    // Keyed by (source, dest, identification, protocol number), as in RFC 791
+    pending: HashMap<(IpAddr, IpAddr, u16, u8), Pending>,
+    dropped: usize,
+}
+
+struct Pending {
+    first_seen: SystemTime,
+    // The length of the whole payload, once the last fragment has arrived
+    length: Option<usize>,
+    // By offset
+    fragments: BTreeMap<usize, Vec<u8>>,
+    // The first fragment, for the rest of the reassembled packet's fields
+    template: OwnedIpPacket,
+}
+
+impl Reassembler {
+    /// 30 seconds, as Linux uses by default.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: HashMap::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Adds a packet received at `timestamp`, returning the whole packet if it's now complete.
+    ///
+    /// Packets that aren't fragmented are returned straight away (copied, for consistency), so
+    /// every packet in a capture can be passed through this.
+    pub fn add(&mut self, packet: &IpPacket, timestamp: SystemTime) -> Option<OwnedIpPacket> {
+        self.expire(timestamp);
+        if !packet.is_fragment() {
+            return Some(packet.to_owned());
+        }
+
+        let key = (packet.source, packet.dest, packet.identification, packet.protocol.as_u8());
+        let pending = self.pending.entry(key).or_insert_with(|| Pending {
+            first_seen: timestamp,
+            length: None,
+            fragments: BTreeMap::new(),
+            template: packet.to_owned(),
+        });
+        if packet.fragment_offset == 0 {
+            pending.template = packet.to_owned();
+        }
+        if !packet.more_fragments {
+            pending.length = Some(packet.fragment_offset + packet.declared_length);
+        }
+        // Only what was captured is kept, so a truncated fragment leaves a gap
+        pending.fragments.insert(packet.fragment_offset, packet.data.to_vec());
+
+        let data = pending.reassemble()?;
+        let mut whole = self.pending.remove(&key).unwrap().template;
+        whole.declared_length = data.len();
+        whole.data = data;
+        whole.more_fragments = false;
+        whole.fragment_offset = 0;
+        Some(whole)
+    }
+
+    /// Drops any incomplete packets that have timed out by `now`, which happens anyway as packets
+    /// are added.
+    pub fn expire(&mut self, now: SystemTime) {
+        let before = self.pending.len();
+        let timeout = self.timeout;
+        // A timestamp earlier than the first fragment's (i.e. out of order) doesn't expire anything
+        self.pending
+            .retain(|_, pending| now.duration_since(pending.first_seen).map_or(true, |waited| waited <= timeout));
+        self.dropped += before - self.pending.len();
+    }
+
+    /// How many packets are still waiting for fragments.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// How many packets were dropped as incomplete when they timed out.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+impl Pending {
+    // The whole payload, if every byte of it has arrived
+    fn reassemble(&self) -> Option<Vec<u8>> {
+        let length = self.length?;
+        let mut covered = 0;
+        for (&offset, data) in &self.fragments {
+            if offset > covered {
+                return None;
+            }
+            covered = covered.max(offset + data.len());
+        }
+        if covered < length {
+            return None;
+        }
+        let mut whole = vec![0; length];
+        for (&offset, data) in &self.fragments {
+            // Anything past the end (which a valid packet wouldn't have) is ignored
+            let end = (offset + data.len()).min(length);
+            if offset < end {
+                whole[offset..end].copy_from_slice(&data[..end - offset]);
+            }
+        }
+        Some(whole)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::Protocol;
+
+    const PAYLOAD: &[u8; 24] = b"abcdefghijklmnopqrstuvwx";
+
+    // The fragment of `PAYLOAD` at `range`, as packet `identification`
+    fn fragment(identification: u16, range: std::ops::Range<usize>) -> IpPacket<'static> {
+        IpPacket {
+            data: &PAYLOAD[range.clone()],
+            protocol: Protocol::UDP,
+            source: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 100)),
+            dest: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            declared_length: range.len(),
+            identification,
+            more_fragments: range.end < PAYLOAD.len(),
+            fragment_offset: range.start,
+        }
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    // Adds each fragment in turn, returning the packet it was completed by (if any) and which
+    fn add_all(reassembler: &mut Reassembler, fragments: &[IpPacket]) -> Option<(usize, OwnedIpPacket)> {
+        let mut complete = None;
+        for (i, fragment) in fragments.iter().enumerate() {
+            if let Some(whole) = reassembler.add(fragment, at(0)) {
+                assert!(complete.is_none(), "completed twice");
+                complete = Some((i, whole));
+            }
+        }
+        complete
+    }
+
+    #[test]
+    fn unfragmented() {
+        let mut reassembler = Reassembler::new(Reassembler::DEFAULT_TIMEOUT);
+        let packet = fragment(1, 0..24);
+        assert_eq!(reassembler.add(&packet, at(0)), Some(packet.to_owned()));
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn any_order() {
+        let in_order = [fragment(1, 0..8), fragment(1, 8..16), fragment(1, 16..24)];
+        let reversed = [fragment(1, 16..24), fragment(1, 8..16), fragment(1, 0..8)];
+        for fragments in [in_order, reversed] {
+            let mut reassembler = Reassembler::new(Reassembler::DEFAULT_TIMEOUT);
+            let (i, whole) = add_all(&mut reassembler, &fragments).unwrap();
+            assert_eq!(i, 2);
+            assert_eq!(whole.data, PAYLOAD);
+            assert_eq!(whole.declared_length, 24);
+            assert!(!whole.more_fragments);
+            assert_eq!(whole.fragment_offset, 0);
+            assert_eq!(reassembler.pending(), 0);
+        }
+    }
+
+    #[test]
+    fn duplicates_and_overlaps() {
+        let mut reassembler = Reassembler::new(Reassembler::DEFAULT_TIMEOUT);
+        let fragments = [fragment(1, 0..8), fragment(1, 0..8), fragment(1, 16..24), fragment(1, 4..20)];
+        let (i, whole) = add_all(&mut reassembler, &fragments).unwrap();
+        assert_eq!(i, 3);
+        assert_eq!(whole.data, PAYLOAD);
+
+        // A duplicate after completion starts a new packet rather than returning it again
+        assert_eq!(reassembler.add(&fragments[0], at(0)), None);
+        assert_eq!(reassembler.pending(), 1);
+    }
+
+    #[test]
+    fn separate_packets() {
+        let mut reassembler = Reassembler::new(Reassembler::DEFAULT_TIMEOUT);
+        let fragments = [fragment(1, 0..16), fragment(2, 0..16), fragment(2, 16..24)];
+        let (i, _) = add_all(&mut reassembler, &fragments).unwrap();
+        assert_eq!(i, 2);
+        assert_eq!(reassembler.pending(), 1);
+    }
+
+    #[test]
+    fn missing_last_fragment() {
+        let mut reassembler = Reassembler::new(Duration::from_secs(30));
+        assert_eq!(add_all(&mut reassembler, &[fragment(1, 0..8), fragment(1, 8..16)]), None);
+        reassembler.expire(at(30));
+        assert_eq!(reassembler.pending(), 1);
+        assert_eq!(reassembler.dropped(), 0);
+        reassembler.expire(at(31));
+        assert_eq!(reassembler.pending(), 0);
+        assert_eq!(reassembler.dropped(), 1);
+
+        // Too late to complete it, so the last fragment is pending on its own
+        assert_eq!(reassembler.add(&fragment(1, 16..24), at(31)), None);
+        assert_eq!(reassembler.pending(), 1);
+    }
+
+    #[test]
+    fn truncated_fragment() {
+        let mut reassembler = Reassembler::new(Duration::from_secs(30));
+        let mut truncated = fragment(1, 0..16);
+        truncated.data = &truncated.data[..12];
+        assert_eq!(add_all(&mut reassembler, &[truncated, fragment(1, 16..24)]), None);
+        // It waits out the timeout like any other incomplete packet
+        assert_eq!(reassembler.pending(), 1);
+        assert_eq!(reassembler.add(&fragment(2, 0..24), at(40)).map(|whole| whole.data), Some(PAYLOAD.to_vec()));
+        assert_eq!(reassembler.pending(), 0);
+        assert_eq!(reassembler.dropped(), 1);
+    }
+
+    #[test]
+    fn dropped_counts() {
+        let mut reassembler = Reassembler::new(Duration::from_secs(10));
+        reassembler.add(&fragment(1, 0..8), at(0));
+        reassembler.add(&fragment(2, 0..8), at(5));
+        reassembler.add(&fragment(3, 8..16), at(5));
+        // Out of order timestamps don't expire anything
+        reassembler.expire(at(0));
+        reassembler.expire(at(11));
+        assert_eq!((reassembler.pending(), reassembler.dropped()), (2, 1));
+        reassembler.add(&fragment(4, 0..8), at(16));
+        assert_eq!((reassembler.pending(), reassembler.dropped()), (1, 3));
+    }
+}
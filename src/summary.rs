@@ -0,0 +1,92 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    net::IpAddr,
+};
+
+use crate::{IpPacket, Protocol};
+
+/// Running totals over a stream of IP packets, the statistics that both the analyzer and the
+/// brainfuck version of it report.
+///
+/// Its `Display` is the short report that `program.bf` prints.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub packets: usize,
+    pub udp_packets: usize,
+    /// IP-level data, i.e. everything after the IP headers
+    pub ip_data: usize,
+    /// The part of `ip_data` carried by UDP packets
+    pub udp_ip_data: usize,
+    /// Application-level data, with malformed transport headers counting as none
+    pub tcp_payload_data: usize,
+    pub udp_payload_data: usize,
+    // In the order they were first seen, which breaks ties as `program.bf` does
+    destinations: Vec<(IpAddr, usize)>,
+    destination_index: HashMap<IpAddr, usize>,
+}
+
+impl Summary {
+    pub fn add(&mut self, packet: &IpPacket) {
+        self.add_totals(packet);
+        let i = *self.destination_index.entry(packet.dest).or_insert_with(|| {
+            self.destinations.push((packet.dest, 0));
+            self.destinations.len() - 1
+        });
+        self.destinations[i].1 += 1;
+    }
+
+    /// As [`Summary::add`], but without counting the destination, for when destinations are counted
+    /// some other way (e.g. approximately).
+    pub fn add_totals(&mut self, packet: &IpPacket) {
+        self.packets += 1;
+        self.ip_data += packet.data.len();
+        match packet.protocol {
+            Protocol::TCP => self.tcp_payload_data += packet.tcp_payload().map_or(0, <[u8]>::len),
+            Protocol::UDP => {
+                self.udp_packets += 1;
+                self.udp_ip_data += packet.data.len();
+                self.udp_payload_data += packet.udp_payload().map_or(0, <[u8]>::len);
+            }
+        }
+    }
+
+    pub fn tcp_packets(&self) -> usize {
+        self.packets - self.udp_packets
+    }
+
+    /// How many packets went to each destination, in the order each was first seen.
+    pub fn destinations(&self) -> &[(IpAddr, usize)] {
+        &self.destinations
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.packets == 0 {
+            return writeln!(f, "Capture contains no IP packets");
+        }
+        writeln!(f, "Total IP-level data: {} bytes", self.ip_data)?;
+        writeln!(f, "{} UDP, {} TCP", self.udp_packets, self.tcp_packets())?;
+        writeln!(f, "{} bytes over TCP, {} bytes over UDP", self.ip_data - self.udp_ip_data, self.udp_ip_data)?;
+        writeln!(f, "Average of {} bytes/packet", self.ip_data / self.packets)?;
+
+        let count = self.destinations.iter().map(|&(_, n)| n).max().unwrap_or(0);
+        let mut most_popular = self.destinations.iter().filter(|&&(_, n)| n == count);
+        let Some((ip, _)) = most_popular.next() else {
+            return Ok(());
+        };
+        let others = most_popular.count();
+        let s = |n| if n == 1 { "" } else { "s" };
+        if others == 0 {
+            writeln!(f, "Most popular destination was {ip} with {count} packet{}", s(count))
+        } else {
+            writeln!(
+                f,
+                "Most popular destinations were {ip} and {others} other{} with {count} packet{} each",
+                s(others),
+                s(count)
+            )
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::{io::Write, time::SystemTime};
+
+use anyhow::{bail, Context};
+
+/// Writes a pcap capture that [`Capture::new`](crate::Capture::new) can read back, e.g. to build a
+/// capture from frames made up on the spot.
+///
+/// Timestamps are written in microseconds, and frames longer than the snap length are cut short
+/// to it, as a capture tool would (see [`PhysicalFrame::was_truncated`](crate::PhysicalFrame::was_truncated)).
+pub struct CaptureWriter<W> {
+    w: W,
+    snap_len: u32,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// The snap length written to the header by [`CaptureWriter::new`], the most bytes kept of a
+    /// single frame.
+    pub const SNAP_LEN: u32 = 262144;
+
+    /// Writes the header, where `link_type` is the `LINKTYPE_` value of the frames, e.g. `1` for
+    /// Ethernet or `101` for raw IP.
+    pub fn new(w: W, link_type: u32) -> anyhow::Result<Self> {
+        Self::with_snap_len(w, link_type, Self::SNAP_LEN)
+    }
+
+    /// As [`CaptureWriter::new`], but only keeping the first `snap_len` bytes of each frame, e.g.
+    /// 96 for a capture of just the headers.
+    pub fn with_snap_len(mut w: W, link_type: u32, snap_len: u32) -> anyhow::Result<Self> {
+        if snap_len == 0 {
+            bail!("snap length must be nonzero");
+        }
+        w.write_all(&0xA1B2C3D4_u32.to_le_bytes())?;
+        w.write_all(&2_u16.to_le_bytes())?;
+        w.write_all(&4_u16.to_le_bytes())?;
+        w.write_all(&[0; 8])?; // Reserved
+        w.write_all(&snap_len.to_le_bytes())?;
+        w.write_all(&link_type.to_le_bytes())?;
+        Ok(Self { w, snap_len })
+    }
+
+    pub fn write_frame(&mut self, timestamp: SystemTime, data: &[u8]) -> anyhow::Result<()> {
+        let since_epoch = timestamp.duration_since(SystemTime::UNIX_EPOCH).context("timestamp is before 1970")?;
+        let Ok(seconds) = u32::try_from(since_epoch.as_secs()) else {
+            bail!("timestamp is too late for pcap's 32-bit seconds");
+        };
+        let Ok(original) = u32::try_from(data.len()) else {
+            bail!("frame of {} bytes is too long for pcap's 32-bit lengths", data.len());
+        };
+        let captured = original.min(self.snap_len);
+        self.w.write_all(&seconds.to_le_bytes())?;
+        self.w.write_all(&since_epoch.subsec_micros().to_le_bytes())?;
+        self.w.write_all(&captured.to_le_bytes())?;
+        self.w.write_all(&original.to_le_bytes())?;
+        self.w.write_all(&data[..captured as usize])?;
+        Ok(())
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn into_inner(mut self) -> anyhow::Result<W> {
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}